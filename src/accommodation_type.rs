@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Read;
+
+use enum_map::EnumMap;
+use log::error;
+
+use crate::census_table::check_totals;
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+/// The column holding the output area code in the wide format download
+const GEOGRAPHY_COLUMN: &str = "geography code";
+const DATE_COLUMN: &str = "date";
+/// The columns before the dwelling counts: date, geography, geography code and rural urban
+const FIRST_DWELLING_COLUMN: usize = 4;
+
+/// The cells of QS402EW (`NM_534_1`), the accommodation type of every household space
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Enum)]
+pub enum DwellingType {
+    All,
+    Unshared,
+    WholeHouse,
+    DetachedHouse,
+    SemiDetachedHouse,
+    TerracedHouse,
+    FlatMaisonetteApartment,
+    BlockOfFlats,
+    SharedHouse,
+    Commercial,
+    Temporary,
+    SharedDwelling,
+}
+
+impl DwellingType {
+    /// The cell one level up, such as `WholeHouse` for `DetachedHouse`
+    pub fn parent(self) -> Option<DwellingType> {
+        use DwellingType::*;
+        match self {
+            All => None,
+            Unshared | SharedDwelling => Some(All),
+            WholeHouse | FlatMaisonetteApartment | Temporary => Some(Unshared),
+            DetachedHouse | SemiDetachedHouse | TerracedHouse => Some(WholeHouse),
+            BlockOfFlats | SharedHouse | Commercial => Some(FlatMaisonetteApartment),
+        }
+    }
+}
+
+impl TryFrom<&str> for DwellingType {
+    type Error = ParsingError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        use DwellingType::*;
+        match value {
+            "Dwelling Type: All categories: Accommodation type; measures: Value" => Ok(All),
+            "Dwelling Type: Unshared dwelling: Total; measures: Value" => Ok(Unshared),
+            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Total; measures: Value" => Ok(WholeHouse),
+            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Detached; measures: Value" => Ok(DetachedHouse),
+            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Semi-detached; measures: Value" => Ok(SemiDetachedHouse),
+            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Terraced (including end-terrace); measures: Value" => Ok(TerracedHouse),
+            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: Total; measures: Value" => Ok(FlatMaisonetteApartment),
+            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: Purpose-built block of flats or tenement; measures: Value" => Ok(BlockOfFlats),
+            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: Part of a converted or shared house (including bed-sits); measures: Value" => Ok(SharedHouse),
+            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: In commercial building; measures: Value" => Ok(Commercial),
+            "Dwelling Type: Unshared dwelling: Caravan or other mobile or temporary structure; measures: Value" => Ok(Temporary),
+            "Dwelling Type: Shared dwelling; measures: Value" => Ok(SharedDwelling),
+            _ => Err(ParsingError::new(ParsingErrorType::InvalidDataType(value.to_string()), Some(format!("Cannot convert value ({}) to Dwelling type", value))))
+        }
+    }
+}
+
+/// The household spaces of a single output area by accommodation type
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccommodationTypeRecord {
+    pub date: String,
+    pub geography_code: String,
+    pub dwellings: EnumMap<DwellingType, u32>,
+}
+
+impl AccommodationTypeRecord {
+    /// The number of household spaces, from the "All categories" column
+    pub fn total(&self) -> u32 {
+        self.dwellings[DwellingType::All]
+    }
+}
+
+/// Parses a wide format QS402EW download, with one row per output area and one column per dwelling type
+///
+/// Fails if a column isn't a known dwelling type, and skips (and logs) any area whose counts don't add up to its "All categories" total
+pub fn read_accommodation_types<R: Read>(mut data: csv::Reader<R>) -> Result<HashMap<String, AccommodationTypeRecord>, ParsingError> {
+    let headers = data.headers()?.clone();
+    let column_index = |name: &str| headers.iter().position(|header| header == name)
+        .ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(format!("No {} column", name))));
+    let date_index = column_index(DATE_COLUMN)?;
+    let geography_index = column_index(GEOGRAPHY_COLUMN)?;
+    let columns = headers.iter().enumerate().skip(FIRST_DWELLING_COLUMN)
+        .map(|(index, header)| Ok((index, DwellingType::try_from(header)?)))
+        .collect::<Result<Vec<(usize, DwellingType)>, ParsingError>>()?;
+    let mut output = HashMap::new();
+    for record in data.records() {
+        let record = record?;
+        let mut dwellings = EnumMap::default();
+        for (index, dwelling_type) in &columns {
+            dwellings[*dwelling_type] = record.get(*index).unwrap_or_default().trim().parse()?;
+        }
+        let geography_code = record.get(geography_index).unwrap_or_default().to_string();
+        if let Err(e) = check_totals(&geography_code, &dwellings, DwellingType::parent) {
+            error!("{}", e);
+            continue;
+        }
+        output.insert(geography_code.clone(), AccommodationTypeRecord { date: record.get(date_index).unwrap_or_default().to_string(), geography_code, dwellings });
+    }
+    Ok(output)
+}
+
+/// Reads a wide format QS402EW file, such as `data/census_data/accomodation_type_london.csv`
+pub fn read_accommodation_types_file(filename: &str) -> Result<HashMap<String, AccommodationTypeRecord>, ParsingError> {
+    read_accommodation_types(csv::Reader::from_path(filename)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_accommodation_types, DwellingType};
+
+    const LONDON: &str = include_str!("../data/census_data/accomodation_type_london.csv");
+
+    #[test]
+    fn reads_london_accommodation_types() {
+        let records = read_accommodation_types(csv::Reader::from_reader(LONDON.as_bytes())).unwrap();
+        assert_eq!(records.len(), 25053);
+        let record = &records["E00004482"];
+        assert_eq!(record.date, "2011");
+        assert_eq!(record.total(), 121);
+        assert_eq!(record.dwellings[DwellingType::TerracedHouse], 29);
+        assert_eq!(record.dwellings[DwellingType::SharedDwelling], 2);
+    }
+
+    #[test]
+    fn skips_areas_not_matching_their_total() {
+        let mut lines = LONDON.lines();
+        let data = format!("{}\n\"2011\",\"E00004482\",\"E00004482\",\"Total\",122,119,47,7,11,29,72,23,49,0,0,2\n", lines.next().unwrap());
+        assert!(read_accommodation_types(csv::Reader::from_reader(data.as_bytes())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_columns() {
+        let data = "\"date\",\"geography\",\"geography code\",\"Rural Urban\",\"Dwelling Type: Boat; measures: Value\"\n\"2011\",\"E00004482\",\"E00004482\",\"Total\",1\n";
+        assert!(read_accommodation_types(csv::Reader::from_reader(data.as_bytes())).is_err());
+    }
+}
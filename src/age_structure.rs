@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+
+use crate::census_table::CensusTable;
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+use crate::population_and_density_per_output_area::PreProcessingRecord;
+
+/// The last age in QS103EW, which also counts everyone older
+pub const OLDEST_AGE: usize = 100;
+
+/// A cell of QS103EW, such as "Age under 1", "Age 42" or "Age 100 and over"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgeCell {
+    All,
+    Age(usize),
+}
+
+impl<'de> Deserialize<'de> for AgeCell {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let cell_name = String::deserialize(deserializer)?;
+        match cell_name.as_str() {
+            "All categories: Age" => Ok(AgeCell::All),
+            "Age under 1" => Ok(AgeCell::Age(0)),
+            _ => {
+                let age = cell_name.strip_prefix("Age ").map(|age| age.trim_end_matches(" and over"))
+                    .and_then(|age| age.parse().ok())
+                    .filter(|age| *age <= OLDEST_AGE)
+                    .ok_or_else(|| D::Error::custom(format!("Unknown age cell: {}", cell_name)))?;
+                Ok(AgeCell::Age(age))
+            }
+        }
+    }
+}
+
+/// The usual residents of a single area by single year of age, from QS103EW (`NM_503_1`)
+///
+/// The geography code is the same output area code that `Map` uses as `Area.label`
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgeStructureRecord {
+    pub geography_code: String,
+    pub geography_type: String,
+    pub total: u32,
+    /// Indexed by age, where the last entry is everyone aged `OLDEST_AGE` and over
+    pub counts: [u32; OLDEST_AGE + 1],
+}
+
+impl AgeStructureRecord {
+    /// The number of residents in the band, which is 0 for a band with its upper age below its lower age
+    pub fn band_count(&self, band: &AgeBand) -> u32 {
+        let upper = band.upper.unwrap_or(OLDEST_AGE).min(OLDEST_AGE);
+        self.counts.get(band.lower.min(OLDEST_AGE)..=upper).map_or(0, |counts| counts.iter().sum())
+    }
+    /// The number of residents in each band, in the order of the bands
+    pub fn band_counts(&self, bands: &AgeBands) -> Vec<u32> {
+        bands.bands.iter().map(|band| self.band_count(band)).collect()
+    }
+}
+
+impl CensusTable for AgeStructureRecord {
+    const TABLE_ID: &'static str = "NM_503_1";
+    type Cell = AgeCell;
+    type Value = u32;
+
+    fn new(geography_code: String, geography_type: String) -> Self {
+        AgeStructureRecord { geography_code, geography_type, total: 0, counts: [0; OLDEST_AGE + 1] }
+    }
+    fn geography_code(&self) -> &str {
+        &self.geography_code
+    }
+    fn add(&mut self, record: &PreProcessingRecord, cell: AgeCell, value: u32) -> Result<(), ParsingError> {
+        if record.rural_urban_name != "Total" {
+            return Ok(());
+        }
+        match cell {
+            AgeCell::All => self.total = value,
+            AgeCell::Age(age) => self.counts[age] = value,
+        }
+        Ok(())
+    }
+    fn validate(&self) -> Result<(), ParsingError> {
+        let sum: u32 = self.counts.iter().sum();
+        if sum != self.total {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Age total")), Some(format!("Ages in {} add up to {}, but the total is {}", self.geography_code, sum, self.total))));
+        }
+        Ok(())
+    }
+}
+
+/// An inclusive range of ages, where `upper` of `None` means every age from `lower` up
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AgeBand {
+    pub lower: usize,
+    pub upper: Option<usize>,
+}
+
+impl AgeBand {
+    /// Fails if `upper` is below `lower`
+    pub fn new(lower: usize, upper: Option<usize>) -> Result<AgeBand, ParsingError> {
+        if upper.is_some_and(|upper| upper < lower) {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Age band")), Some(format!("Age band upper age {:?} is below its lower age {}", upper, lower))));
+        }
+        Ok(AgeBand { lower, upper })
+    }
+}
+
+impl Display for AgeBand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.upper {
+            Some(upper) => write!(f, "{}-{}", self.lower, upper),
+            None => write!(f, "{}+", self.lower),
+        }
+    }
+}
+
+/// Consecutive age bands covering every age
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AgeBands {
+    pub bands: Vec<AgeBand>,
+}
+
+impl AgeBands {
+    /// 0-4, 5-17, 18-64 and 65+
+    pub fn standard() -> AgeBands {
+        AgeBands::from_lower_bounds(&[0, 5, 18, 65]).expect("Standard age bands are valid")
+    }
+    /// Builds bands starting at each of the given ages, such as `[0, 5, 18, 65]`
+    ///
+    /// The ages must start at 0, be increasing, and be no more than `OLDEST_AGE`
+    pub fn from_lower_bounds(lower_bounds: &[usize]) -> Result<AgeBands, ParsingError> {
+        if lower_bounds.first() != Some(&0) {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Age bands")), Some(format!("Age bands must start at 0: {:?}", lower_bounds))));
+        }
+        if lower_bounds.windows(2).any(|pair| pair[0] >= pair[1]) || lower_bounds.iter().any(|lower| *lower > OLDEST_AGE) {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Age bands")), Some(format!("Age bands must be increasing, up to {}: {:?}", OLDEST_AGE, lower_bounds))));
+        }
+        let bands = lower_bounds.iter().enumerate()
+            .map(|(index, lower)| AgeBand { lower: *lower, upper: lower_bounds.get(index + 1).map(|next| next - 1) })
+            .collect();
+        Ok(AgeBands { bands })
+    }
+    /// The labels of each band, such as "0-4" and "65+"
+    pub fn labels(&self) -> Vec<String> {
+        self.bands.iter().map(|band| band.to_string()).collect()
+    }
+}
+
+/// The number of residents in each band for every area, keyed by geography code
+pub fn band_counts_by_area(records: &HashMap<String, AgeStructureRecord>, bands: &AgeBands) -> HashMap<String, Vec<u32>> {
+    records.iter().map(|(code, record)| (code.clone(), record.band_counts(bands))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::census_table::tests::parse_rows;
+
+    use super::{band_counts_by_area, AgeBand, AgeBands, AgeStructureRecord, OLDEST_AGE};
+
+    /// A QS103EW download for one area, where everyone aged `n` has `n % 3 + 1` residents
+    fn area_rows(code: &str, total: u32) -> String {
+        let mut cells = vec![(String::from("All categories: Age"), total)];
+        for age in 0..=OLDEST_AGE {
+            let name = match age {
+                0 => String::from("Age under 1"),
+                OLDEST_AGE => format!("Age {} and over", age),
+                _ => format!("Age {}", age),
+            };
+            cells.push((name, age as u32 % 3 + 1));
+        }
+        cells.iter().map(|(name, value)| {
+            format!("{},2011 output areas,Total,{},Value,{},A,0,0\n{},2011 output areas,Urban (total),{},Value,0,A,0,0\n", code, name, value, code, name)
+        }).collect()
+    }
+
+    fn parse(data: &str) -> HashMap<String, AgeStructureRecord> {
+        parse_rows(data)
+    }
+
+    #[test]
+    fn parses_single_years_of_age_into_bands() {
+        let expected_total = (0..=OLDEST_AGE as u32).map(|age| age % 3 + 1).sum();
+        let records = parse(&area_rows("E00000001", expected_total));
+        let record = &records["E00000001"];
+        assert_eq!((record.counts[0], record.counts[1], record.counts[OLDEST_AGE]), (1, 2, 2));
+
+        let bands = AgeBands::standard();
+        assert_eq!(bands.labels(), vec!["0-4", "5-17", "18-64", "65+"]);
+        let counts = band_counts_by_area(&records, &bands);
+        assert_eq!(counts["E00000001"][0], 1 + 2 + 3 + 1 + 2);
+        assert_eq!(counts["E00000001"].iter().sum::<u32>(), expected_total);
+    }
+
+    #[test]
+    fn skips_areas_whose_ages_dont_add_up() {
+        assert!(parse(&area_rows("E00000001", 1)).is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_bands() {
+        assert!(AgeBands::from_lower_bounds(&[5, 18]).is_err());
+        assert!(AgeBands::from_lower_bounds(&[0, 18, 18]).is_err());
+        assert!(AgeBands::from_lower_bounds(&[0, 101]).is_err());
+        assert_eq!(AgeBands::from_lower_bounds(&[0, 100]).unwrap().labels(), vec!["0-99", "100+"]);
+
+        assert!(AgeBand::new(18, Some(5)).is_err());
+        assert_eq!(AgeBand::new(5, Some(5)).unwrap().to_string(), "5-5");
+        let expected_total = (0..=OLDEST_AGE as u32).map(|age| age % 3 + 1).sum();
+        let record = &parse(&area_rows("E00000001", expected_total))["E00000001"];
+        assert_eq!(record.band_count(&AgeBand { lower: 18, upper: Some(5) }), 0);
+        assert_eq!(record.band_count(&AgeBand::new(0, Some(1)).unwrap()), 3);
+    }
+}
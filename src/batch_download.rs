@@ -0,0 +1,199 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use crate::download_checkpoint::write_atomically;
+use crate::download_manifest;
+use crate::download_manifest::DownloadManifest;
+use crate::geography::GeographySelector;
+use crate::nomis_download::{DataFetcher, TableInfo};
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+use crate::table_catalogue::{CatalogueQuery, TableCatalogue};
+
+const REPORT_FILENAME: &str = "batch_report.json";
+
+/// Which tables a batch download includes
+#[derive(Clone, Debug)]
+pub enum TableFilter {
+    /// Every table in the catalogue matching the query, such as every table with the source `census_2011_ks`
+    Catalogue(CatalogueQuery),
+    /// The given table ids, such as `NM_144_1`, which are downloaded even if they aren't in the catalogue
+    Ids(Vec<String>),
+}
+
+/// What happened to a single table in a batch download
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOutcome {
+    Succeeded { row_count: usize },
+    /// The table had already been downloaded, and still matched its manifest
+    Skipped { row_count: usize },
+    Failed { error: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub table_id: String,
+    pub outcome: BatchOutcome,
+}
+
+/// A summary of every table in a batch download, saved as `batch_report.json` in the output directory
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BatchReport {
+    pub results: Vec<BatchResult>,
+}
+
+impl BatchReport {
+    pub fn succeeded(&self) -> Vec<&BatchResult> {
+        self.results.iter().filter(|result| matches!(result.outcome, BatchOutcome::Succeeded { .. })).collect()
+    }
+    pub fn skipped(&self) -> Vec<&BatchResult> {
+        self.results.iter().filter(|result| matches!(result.outcome, BatchOutcome::Skipped { .. })).collect()
+    }
+    pub fn failed(&self) -> Vec<&BatchResult> {
+        self.results.iter().filter(|result| matches!(result.outcome, BatchOutcome::Failed { .. })).collect()
+    }
+}
+
+impl Display for BatchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} tables succeeded, {} skipped, {} failed", self.succeeded().len(), self.skipped().len(), self.failed().len())?;
+        for result in &self.results {
+            match &result.outcome {
+                BatchOutcome::Succeeded { row_count } => writeln!(f, "  + {}: {} rows", result.table_id, row_count)?,
+                BatchOutcome::Skipped { row_count } => writeln!(f, "  = {}: {} rows already downloaded", result.table_id, row_count)?,
+                BatchOutcome::Failed { error } => writeln!(f, "  ! {}: {}", result.table_id, error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Downloads many tables for the same geography into one directory
+///
+/// Each table is saved as `{id}.csv` with a manifest, with its pages checkpointed in `pages/{id}`
+/// Every table shares the `DataFetcher`'s rate limiter, so the batch as a whole stays within the NOMIS fair use terms
+pub struct BatchDownload {
+    geography: GeographySelector,
+    page_size: usize,
+    directory: PathBuf,
+}
+
+impl BatchDownload {
+    pub fn new(geography: GeographySelector, page_size: usize, directory: &str) -> BatchDownload {
+        BatchDownload { geography, page_size, directory: PathBuf::from(directory) }
+    }
+    /// The tables the filter selects, with their catalogue entries if they have one
+    pub fn select_tables<'a>(catalogue: &'a TableCatalogue, filter: &TableFilter) -> Vec<(String, Option<&'a TableInfo>)> {
+        match filter {
+            TableFilter::Catalogue(query) => catalogue.search(query).into_iter().map(|table| (table.id.clone(), Some(table))).collect(),
+            TableFilter::Ids(ids) => ids.iter().map(|id| (id.clone(), catalogue.get(id))).collect(),
+        }
+    }
+    /// Downloads every selected table in turn, carrying on past failures, and saves the report
+    pub async fn run(&self, fetcher: &DataFetcher, catalogue: &TableCatalogue, filter: &TableFilter) -> Result<BatchReport, ParsingError> {
+        std::fs::create_dir_all(&self.directory)?;
+        let tables = BatchDownload::select_tables(catalogue, filter);
+        let table_count = tables.len();
+        info!("Batch downloading {} tables for {}", table_count, self.geography);
+        let mut report = BatchReport::default();
+        for (index, (id, table)) in tables.into_iter().enumerate() {
+            let outcome = self.download(fetcher, &id, table).await;
+            info!("Table {} ({}/{}): {:?}", id, index + 1, table_count, outcome);
+            report.results.push(BatchResult { table_id: id, outcome });
+        }
+        write_atomically(&self.directory.join(REPORT_FILENAME), serde_json::to_string_pretty(&report)?.as_bytes())?;
+        info!("{}", report);
+        Ok(report)
+    }
+    /// Checks an earlier download was of the same requests as this batch, and still matches its manifest
+    ///
+    /// The page size isn't compared, as a finished download has the same rows whatever its page size
+    /// Unfinished pages do depend on it, but `DownloadCheckpoint::load_or_create` restarts any checkpoint for another page size or request
+    fn reuse_existing(&self, fetcher: &DataFetcher, id: &str, output: &Path) -> Result<DownloadManifest, ParsingError> {
+        let manifest = DownloadManifest::load(output)?;
+        let queries = fetcher.table_queries(id, &self.geography)?;
+        if manifest.geography != self.geography.to_string() || manifest.queries != queries {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Manifest")), Some(format!("{:?} was downloaded for {}, not {}", output, manifest.geography, self.geography))));
+        }
+        download_manifest::verify(output)
+    }
+    async fn download(&self, fetcher: &DataFetcher, id: &str, table: Option<&TableInfo>) -> BatchOutcome {
+        let output = self.directory.join(format!("{}.csv", id));
+        let pages = self.directory.join("pages").join(id);
+        if output.exists() && DownloadManifest::path_for(&output).exists() {
+            match self.reuse_existing(fetcher, id, &output) {
+                Ok(manifest) => return BatchOutcome::Skipped { row_count: manifest.row_count },
+                Err(e) => info!("Downloading {} again, as the existing download can't be used: {}", id, e),
+            }
+        }
+        match fetcher.save_table(id.to_string(), &self.geography, self.page_size, &pages.to_string_lossy(), &output.to_string_lossy(), table).await {
+            Ok((_, manifest)) => BatchOutcome::Succeeded { row_count: manifest.row_count },
+            Err(e) => {
+                error!("Failed to download {}: {}", id, e);
+                BatchOutcome::Failed { error: e.to_string() }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::download_checkpoint::DownloadCheckpoint;
+    use crate::geography::GeographySelector;
+    use crate::nomis_download::DataFetcher;
+    use crate::nomis_stand_in::NomisStandIn;
+    use crate::request_policy::RetryPolicy;
+    use crate::table_catalogue::{CatalogueQuery, TableCatalogue};
+
+    use super::{BatchDownload, BatchOutcome, TableFilter};
+
+    #[tokio::test]
+    async fn downloads_matching_tables_and_skips_completed_ones() {
+        let stand_in = NomisStandIn::start().await;
+        let fetcher = DataFetcher::default().with_base_url(&stand_in.base_url()).with_rate_limit(0.0).with_retry_policy(RetryPolicy::no_retries());
+        let catalogue = TableCatalogue::fetch(&fetcher).await.unwrap();
+        let directory = std::env::temp_dir().join(format!("batch_download_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let batch = BatchDownload::new(GeographySelector::england_output_areas(), 100, directory.to_str().unwrap());
+        let filter = TableFilter::Catalogue(CatalogueQuery::default().with_geography_level("oa"));
+
+        let report = batch.run(&fetcher, &catalogue, &filter).await.unwrap();
+        assert_eq!(report.results.len(), 2);
+        assert_eq!(report.results[0].outcome, BatchOutcome::Succeeded { row_count: 500 });
+        // The stand in only serves NM_144_1
+        assert_eq!(report.failed().len(), 1);
+        assert!(directory.join("batch_report.json").exists());
+
+        let report = batch.run(&fetcher, &catalogue, &TableFilter::Ids(vec!["NM_144_1".to_string()])).await.unwrap();
+        assert_eq!(report.results[0].outcome, BatchOutcome::Skipped { row_count: 500 });
+
+        // The same table for another geography has to be downloaded again
+        let requests = stand_in.requests().len();
+        let batch = BatchDownload::new(GeographySelector::Codes(vec![String::from("E00062207")]), 100, directory.to_str().unwrap());
+        let report = batch.run(&fetcher, &catalogue, &TableFilter::Ids(vec!["NM_144_1".to_string()])).await.unwrap();
+        assert!(matches!(report.results[0].outcome, BatchOutcome::Succeeded { .. }), "{:?}", report.results[0].outcome);
+        assert!(stand_in.requests().len() > requests);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restarts_stale_checkpoints_without_an_output() {
+        let stand_in = NomisStandIn::start().await;
+        let fetcher = DataFetcher::default().with_base_url(&stand_in.base_url()).with_rate_limit(0.0).with_retry_policy(RetryPolicy::no_retries());
+        let catalogue = TableCatalogue::fetch(&fetcher).await.unwrap();
+        let directory = std::env::temp_dir().join(format!("batch_download_stale_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        // An interrupted download of the table for another geography, with another page size
+        let pages = directory.join("pages").join("NM_144_1");
+        let mut checkpoint = DownloadCheckpoint::load_or_create(pages.to_str().unwrap(), "NM_144_1", "E00062207", "NM_144_1.data.csv?geography=E00062207", 50, 150, 3).unwrap();
+        checkpoint.save_page(0, "Not the page of this batch\n").unwrap();
+
+        let batch = BatchDownload::new(GeographySelector::england_output_areas(), 100, directory.to_str().unwrap());
+        let report = batch.run(&fetcher, &catalogue, &TableFilter::Ids(vec!["NM_144_1".to_string()])).await.unwrap();
+        assert_eq!(report.results[0].outcome, BatchOutcome::Succeeded { row_count: 500 });
+        assert_eq!(std::fs::read_to_string(directory.join("NM_144_1.csv")).unwrap(), stand_in.full_table("NM_144_1"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+
+use log::{debug, error};
+use walkdir::WalkDir;
+
+use crate::census_descriptor::TableDescriptor;
+use crate::census_file_name_parse::{CensusFileName, FileKind, GeographyLevel, TableType};
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+/// Where the 2011 census bulk releases can be downloaded from, such as `ks101ew_2011_oa.zip`
+pub const BULK_DOWNLOAD_URL: &str = "https://www.nomisweb.co.uk/output/census/2011/";
+
+/// The URL of the bulk release for a table at a geography level, such as `KS101EW` and `oa`
+pub fn bulk_archive_url(table: &str, geography: GeographyLevel) -> String {
+    format!("{}{}_2011_{}.zip", BULK_DOWNLOAD_URL, table.to_lowercase(), geography.code())
+}
+
+/// Where a bulk file is stored on disk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BulkFileLocation {
+    File(PathBuf),
+    /// A file inside a zip archive, which is read without extracting it
+    Archive { archive: PathBuf, member: String },
+}
+
+/// A single bulk CSV file, with the codes decoded from its name
+#[derive(Clone, Debug)]
+pub struct BulkFileEntry {
+    pub location: BulkFileLocation,
+    pub name: CensusFileName,
+    /// Either from the file name, or the name of the archive it is in, such as `oa` for `ks101ew_2011_oa.zip`
+    pub geography_level: Option<GeographyLevel>,
+}
+
+/// Every bulk census file found in a directory, including those inside zip archives
+#[derive(Clone, Debug, Default)]
+pub struct BulkFileIndex {
+    pub files: Vec<BulkFileEntry>,
+}
+
+impl BulkFileIndex {
+    /// Walks the directory, indexing every CSV and every CSV inside a zip archive
+    ///
+    /// Files with names that can't be decoded, and archives that can't be read, are logged and skipped
+    pub fn from_directory(directory: &str) -> Result<BulkFileIndex, ParsingError> {
+        let mut index = BulkFileIndex::default();
+        for entry in WalkDir::new(directory).sort_by_file_name() {
+            let entry = entry.map_err(|e| ParsingError::new(ParsingErrorType::IOError, Some(e.to_string())))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_lowercase);
+            match extension.as_deref() {
+                Some("csv") => index.add(BulkFileLocation::File(path.to_path_buf()), None),
+                Some("zip") => {
+                    let archive = match File::open(path).map_err(ParsingError::from).and_then(|file| zip::ZipArchive::new(file).map_err(zip_error)) {
+                        Ok(archive) => archive,
+                        Err(e) => {
+                            error!("Skipping {:?}, as it can't be read: {}", path, e);
+                            continue;
+                        }
+                    };
+                    let geography = archive_geography(path);
+                    let members: Vec<String> = archive.file_names().map(str::to_string).collect();
+                    for member in members {
+                        index.add(BulkFileLocation::Archive { archive: path.to_path_buf(), member }, geography);
+                    }
+                }
+                _ => debug!("Skipping {:?}, as it isn't a CSV or zip file", path),
+            }
+        }
+        Ok(index)
+    }
+    fn add(&mut self, location: BulkFileLocation, archive_geography: Option<GeographyLevel>) {
+        let file_name = match &location {
+            BulkFileLocation::File(path) => path.file_name().and_then(|name| name.to_str()).unwrap_or_default(),
+            BulkFileLocation::Archive { member, .. } => member.as_str(),
+        };
+        let name: CensusFileName = match file_name.parse() {
+            Ok(name) => name,
+            Err(e) => {
+                debug!("Skipping {:?}: {}", location, e);
+                return;
+            }
+        };
+        let geography_level = name.geography.or(archive_geography);
+        self.files.push(BulkFileEntry { location, name, geography_level });
+    }
+    /// Every indexed file for the given table, such as `TableType::KeyStatistics` and 101 for `KS101EW`
+    pub fn find(&self, table_type: TableType, table_number: u32) -> Vec<&BulkFileEntry> {
+        self.files.iter().filter(|entry| entry.name.table_type == table_type && entry.name.table_number == table_number).collect()
+    }
+    /// Finds a file by its canonical name, such as `KS101EWDATA.CSV`
+    pub fn get(&self, file_name: &str) -> Option<&BulkFileEntry> {
+        self.files.iter().find(|entry| entry.name.to_string().eq_ignore_ascii_case(file_name))
+    }
+    /// Parses the DESC file for the given DATA file, from the same release
+    pub fn descriptor(&self, data: &BulkFileEntry) -> Result<TableDescriptor, ParsingError> {
+        let descriptor = self.files.iter().find(|entry| {
+            entry.name.kind == FileKind::Description
+                && entry.name.table_name() == data.name.table_name()
+                && entry.geography_level == data.geography_level
+        }).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(format!("No DESC file for {}", data.name))))?;
+        TableDescriptor::from_reader(self.open(descriptor)?)
+    }
+    /// Opens an indexed file as a CSV reader, reading it into memory if it is inside an archive
+    pub fn open(&self, entry: &BulkFileEntry) -> Result<csv::Reader<Box<dyn Read>>, ParsingError> {
+        let reader: Box<dyn Read> = match &entry.location {
+            BulkFileLocation::File(path) => Box::new(File::open(path)?),
+            BulkFileLocation::Archive { archive, member } => {
+                let mut archive = zip::ZipArchive::new(File::open(archive)?).map_err(zip_error)?;
+                let mut file = archive.by_name(member).map_err(zip_error)?;
+                let mut data = Vec::with_capacity(file.size() as usize);
+                file.read_to_end(&mut data)?;
+                Box::new(Cursor::new(data))
+            }
+        };
+        Ok(csv::Reader::from_reader(reader))
+    }
+}
+
+/// The geography level from an archive name, such as `oa` for `ks101ew_2011_oa.zip`
+fn archive_geography(path: &Path) -> Option<GeographyLevel> {
+    let stem = path.file_stem()?.to_str()?;
+    let (_, geography) = stem.rsplit_once('_')?;
+    GeographyLevel::from_code(geography).ok()
+}
+
+fn zip_error(error: zip::result::ZipError) -> ParsingError {
+    ParsingError::new(ParsingErrorType::IOError, Some(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use crate::census_file_name_parse::{Country, GeographyLevel, TableType};
+
+    use super::{BulkFileIndex, BulkFileLocation};
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("census_bulk_files_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn indexes_files_and_archives() {
+        let directory = temp_directory("index");
+        fs::write(directory.join("QS1101EWDATA.CSV"), "GeographyCode,QS1101EW0001\nE00000001,100\n").unwrap();
+        fs::write(directory.join("notes.txt"), "Not a census file").unwrap();
+        fs::write(directory.join("qs101ew_2011_oa.zip"), "Not a zip archive").unwrap();
+        let mut archive = zip::ZipWriter::new(fs::File::create(directory.join("ks101ew_2011_oa.zip")).unwrap());
+        archive.start_file("KS101EWDATA.CSV", zip::write::FileOptions::default()).unwrap();
+        archive.write_all(b"GeographyCode,KS101EW0001\nE00000001,242\n").unwrap();
+        archive.start_file("README.TXT", zip::write::FileOptions::default()).unwrap();
+        archive.finish().unwrap();
+
+        let index = BulkFileIndex::from_directory(directory.to_str().unwrap()).unwrap();
+        assert_eq!(index.files.len(), 2);
+        let key_statistics = index.find(TableType::KeyStatistics, 101);
+        assert_eq!(key_statistics.len(), 1);
+        assert_eq!(key_statistics[0].name.country, Country::EnglandAndWales);
+        assert_eq!(key_statistics[0].geography_level, Some(GeographyLevel::OutputArea));
+        assert!(matches!(key_statistics[0].location, BulkFileLocation::Archive { .. }));
+
+        let mut reader = index.open(key_statistics[0]).unwrap();
+        assert_eq!(reader.headers().unwrap().get(1), Some("KS101EW0001"));
+        assert_eq!(reader.records().next().unwrap().unwrap().get(1), Some("242"));
+        let mut reader = index.open(index.get("qs1101ewdata.csv").unwrap()).unwrap();
+        assert_eq!(reader.records().count(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde::Deserialize;
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+/// The column holding the area code in every bulk DATA file
+const GEOGRAPHY_COLUMN: &str = "GeographyCode";
+
+/// A row of a bulk DESC file
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DescriptorRecord {
+    column_variable_code: String,
+    column_variable_measurement_unit: String,
+    column_variable_statistical_unit: String,
+    column_variable_description: String,
+}
+
+/// The columns of a NOMIS `-all_columns` CSV download needed to describe each cell
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct NomisCellRecord {
+    cell_code: String,
+    cell_name: String,
+}
+
+/// The label of a single column of a bulk DATA file, such as `KS101EW0002` for "Males"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColumnDescriptor {
+    pub code: String,
+    pub description: String,
+    /// The levels of the description, such as `["Sex", "Males"]` for "Sex: Males"
+    pub path: Vec<String>,
+    pub measurement_unit: Option<String>,
+    pub statistical_unit: Option<String>,
+}
+
+impl ColumnDescriptor {
+    fn new(code: String, description: String, measurement_unit: Option<String>, statistical_unit: Option<String>) -> ColumnDescriptor {
+        let path = description.split(": ").map(|level| level.trim().to_string()).collect();
+        ColumnDescriptor { code, description, path, measurement_unit, statistical_unit }
+    }
+    /// The most specific level of the description, such as "Males"
+    pub fn label(&self) -> &str {
+        self.path.last().map(String::as_str).unwrap_or(&self.description)
+    }
+}
+
+/// The column code to label map for a single bulk table, in column order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TableDescriptor {
+    pub columns: Vec<ColumnDescriptor>,
+}
+
+impl TableDescriptor {
+    /// Parses a bulk DESC file, such as `KS101EWDESC0.CSV`
+    pub fn from_reader<R: Read>(mut data: csv::Reader<R>) -> Result<TableDescriptor, ParsingError> {
+        let mut columns = Vec::new();
+        for record in data.deserialize() {
+            let record: DescriptorRecord = record?;
+            columns.push(ColumnDescriptor::new(
+                record.column_variable_code,
+                record.column_variable_description,
+                Some(record.column_variable_measurement_unit).filter(|unit| !unit.is_empty()),
+                Some(record.column_variable_statistical_unit).filter(|unit| !unit.is_empty()),
+            ));
+        }
+        Ok(TableDescriptor { columns })
+    }
+    /// Builds the descriptor from the `CELL_CODE` and `CELL_NAME` columns of a NOMIS `-all_columns` download
+    pub fn from_nomis_columns<R: Read>(mut data: csv::Reader<R>) -> Result<TableDescriptor, ParsingError> {
+        let mut columns: Vec<ColumnDescriptor> = Vec::new();
+        for record in data.deserialize() {
+            let record: NomisCellRecord = record?;
+            if !columns.iter().any(|column| column.code == record.cell_code) {
+                columns.push(ColumnDescriptor::new(record.cell_code, record.cell_name, None, None));
+            }
+        }
+        Ok(TableDescriptor { columns })
+    }
+    pub fn get(&self, code: &str) -> Option<&ColumnDescriptor> {
+        self.columns.iter().find(|column| column.code == code)
+    }
+    pub fn get_by_description(&self, description: &str) -> Option<&ColumnDescriptor> {
+        self.columns.iter().find(|column| column.description == description)
+    }
+    /// The column one level up the hierarchy, such as "Sex" for "Sex: Males", if the table has it
+    pub fn parent(&self, code: &str) -> Option<&ColumnDescriptor> {
+        let path = &self.get(code)?.path;
+        if path.len() < 2 {
+            return None;
+        }
+        self.columns.iter().find(|column| column.path[..] == path[..path.len() - 1])
+    }
+    pub fn children(&self, code: &str) -> Vec<&ColumnDescriptor> {
+        match self.get(code) {
+            Some(parent) => self.columns.iter().filter(|column| column.path.len() == parent.path.len() + 1 && column.path.starts_with(&parent.path)).collect(),
+            None => Vec::new(),
+        }
+    }
+    /// Reads every row of a bulk DATA file, labelling each cell with its descriptor
+    ///
+    /// Fails if the file has a column that isn't in this descriptor
+    pub fn read_labelled_rows<R: Read>(&self, mut data: csv::Reader<R>) -> Result<Vec<LabelledRow>, ParsingError> {
+        let headers = data.headers()?.clone();
+        let geography_index = headers.iter().position(|header| header == GEOGRAPHY_COLUMN)
+            .ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(format!("No {} column", GEOGRAPHY_COLUMN))))?;
+        let mut columns = Vec::new();
+        for (index, header) in headers.iter().enumerate() {
+            // The columns before the cells, such as the date and area name, aren't described
+            if index <= geography_index {
+                continue;
+            }
+            match self.get(header) {
+                Some(column) => columns.push((index, column)),
+                // Some releases have extra named columns after the area code, such as "Rural Urban"
+                None if !header.chars().any(|c| c.is_ascii_digit()) => {}
+                None => return Err(ParsingError::new(ParsingErrorType::MissingKey, Some(format!("Column {} has no descriptor", header)))),
+            }
+        }
+        let mut rows = Vec::new();
+        for record in data.records() {
+            let record = record?;
+            let mut cells = Vec::with_capacity(columns.len());
+            for (index, column) in &columns {
+                let value = record.get(*index).unwrap_or_default();
+                cells.push(LabelledCell { column: (*column).clone(), value: value.trim().parse()? });
+            }
+            rows.push(LabelledRow { geography_code: record.get(geography_index).unwrap_or_default().to_string(), cells });
+        }
+        Ok(rows)
+    }
+}
+
+/// A single value of a bulk DATA row, with its label
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelledCell {
+    pub column: ColumnDescriptor,
+    pub value: f64,
+}
+
+/// A single area of a bulk DATA file
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelledRow {
+    pub geography_code: String,
+    pub cells: Vec<LabelledCell>,
+}
+
+impl LabelledRow {
+    /// The value of the cell with the given full description, such as "Sex: Males"
+    pub fn get(&self, description: &str) -> Option<f64> {
+        self.cells.iter().find(|cell| cell.column.description == description).map(|cell| cell.value)
+    }
+    /// Every value keyed by column code
+    pub fn by_code(&self) -> HashMap<&str, f64> {
+        self.cells.iter().map(|cell| (cell.column.code.as_str(), cell.value)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TableDescriptor;
+
+    const DESCRIPTOR: &str = "ColumnVariableCode,ColumnVariableMeasurementUnit,ColumnVariableStatisticalUnit,ColumnVariableDescription
+KS101EW0001,Count,Person,All categories: Sex
+KS101EW0002,Count,Person,All categories: Sex: Males
+KS101EW0003,Count,Person,All categories: Sex: Females
+";
+    const DATA: &str = "date,geography,GeographyCode,KS101EW0001,KS101EW0002,KS101EW0003
+2011,Darlington 001A,E00062207,242,116,126
+";
+
+    #[test]
+    fn labels_bulk_data_rows() {
+        let descriptor = TableDescriptor::from_reader(csv::Reader::from_reader(DESCRIPTOR.as_bytes())).unwrap();
+        assert_eq!(descriptor.get("KS101EW0002").unwrap().label(), "Males");
+        assert_eq!(descriptor.get("KS101EW0002").unwrap().measurement_unit.as_deref(), Some("Count"));
+        assert_eq!(descriptor.parent("KS101EW0003").unwrap().code, "KS101EW0001");
+        assert_eq!(descriptor.children("KS101EW0001").len(), 2);
+
+        let rows = descriptor.read_labelled_rows(csv::Reader::from_reader(DATA.as_bytes())).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].geography_code, "E00062207");
+        assert_eq!(rows[0].get("All categories: Sex: Females"), Some(126.0));
+        assert_eq!(rows[0].by_code()["KS101EW0001"], 242.0);
+    }
+
+    #[test]
+    fn rejects_undescribed_columns() {
+        let descriptor = TableDescriptor::from_reader(csv::Reader::from_reader(DESCRIPTOR.as_bytes())).unwrap();
+        let data = "GeographyCode,KS101EW0001,KS101EW0009\nE00062207,242,1\n";
+        assert!(descriptor.read_labelled_rows(csv::Reader::from_reader(data.as_bytes())).is_err());
+    }
+
+    #[test]
+    fn describes_nomis_cell_codes() {
+        let data = include_str!("../data/download/PopulationAndDensityPerEnglandOutputArea(144)-all_columns.csv");
+        let descriptor = TableDescriptor::from_nomis_columns(csv::Reader::from_reader(data.as_bytes())).unwrap();
+        assert_eq!(descriptor.get("KS101EW0001").unwrap().description, "All usual residents");
+        assert_eq!(descriptor.columns.iter().filter(|column| column.code == "KS101EW0001").count(), 1);
+    }
+}
@@ -1,71 +1,284 @@
-use std::fs;
-use walkdir::WalkDir;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
 
 // Data Source: https://www.nomisweb.co.uk/census/2011
-fn table_type_code_to_string(code: &str) -> Result<&str, String> {
-    if code.len() != 2 {
-        return Err(format!("Table Code is not the correct amount of characters {}", code.len()));
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TableType {
+    QuickStatistics,
+    KeyStatistics,
+    DetailedCharacteristics,
+    LocalCharacteristics,
+    WorkdayPopulation,
+    WorkplacePopulation,
+    CommissionedTables,
+}
+
+impl TableType {
+    pub fn from_code(code: &str) -> Result<TableType, ParsingError> {
+        match code {
+            "QS" => Ok(TableType::QuickStatistics),
+            "KS" => Ok(TableType::KeyStatistics),
+            "DC" => Ok(TableType::DetailedCharacteristics),
+            "LC" => Ok(TableType::LocalCharacteristics),
+            "WD" => Ok(TableType::WorkdayPopulation),
+            "WP" => Ok(TableType::WorkplacePopulation),
+            "CT" => Ok(TableType::CommissionedTables),
+            _ => Err(invalid_name("Table type", format!("Unknown table code {:?}", code))),
+        }
+    }
+    pub fn code(&self) -> &'static str {
+        match self {
+            TableType::QuickStatistics => "QS",
+            TableType::KeyStatistics => "KS",
+            TableType::DetailedCharacteristics => "DC",
+            TableType::LocalCharacteristics => "LC",
+            TableType::WorkdayPopulation => "WD",
+            TableType::WorkplacePopulation => "WP",
+            TableType::CommissionedTables => "CT",
+        }
+    }
+}
+
+impl Display for TableType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TableType::QuickStatistics => write!(f, "Quick Statistics"),
+            TableType::KeyStatistics => write!(f, "Key Statistics"),
+            TableType::DetailedCharacteristics => write!(f, "Detailed Characteristics"),
+            TableType::LocalCharacteristics => write!(f, "Local Characteristics"),
+            TableType::WorkdayPopulation => write!(f, "Workday Population"),
+            TableType::WorkplacePopulation => write!(f, "Workplace Population Tables"),
+            TableType::CommissionedTables => write!(f, "Commissioned Tables"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Country {
+    EnglandAndWales,
+    Wales,
+    England,
+}
+
+impl Country {
+    pub fn from_code(code: &str) -> Result<Country, ParsingError> {
+        match code {
+            "EW" => Ok(Country::EnglandAndWales),
+            "WA" => Ok(Country::Wales),
+            "EN" => Ok(Country::England),
+            _ => Err(invalid_name("Country", format!("Unknown country code {:?}", code))),
+        }
+    }
+    pub fn code(&self) -> &'static str {
+        match self {
+            Country::EnglandAndWales => "EW",
+            Country::Wales => "WA",
+            Country::England => "EN",
+        }
+    }
+}
+
+impl Display for Country {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Country::EnglandAndWales => write!(f, "England & Wales"),
+            Country::Wales => write!(f, "Wales"),
+            Country::England => write!(f, "England"),
+        }
+    }
+}
+
+/// The geography level a bulk file is published at, from either its name or the name of its archive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GeographyLevel {
+    OutputArea,
+    LowerLayerSuperOutputArea,
+    MiddleLayerSuperOutputArea,
+    Ward,
+    MergedWard,
+    LocalAuthority,
+    ParliamentaryConstituency,
+    Region,
+    Country,
+}
+
+impl GeographyLevel {
+    /// Parses a geography code, such as `oa` or `lsoa`, also accepting the short `r` and `ls` forms
+    pub fn from_code(code: &str) -> Result<GeographyLevel, ParsingError> {
+        match code.to_lowercase().as_str() {
+            "oa" => Ok(GeographyLevel::OutputArea),
+            "lsoa" | "ls" => Ok(GeographyLevel::LowerLayerSuperOutputArea),
+            "msoa" => Ok(GeographyLevel::MiddleLayerSuperOutputArea),
+            "ward" => Ok(GeographyLevel::Ward),
+            "merward" => Ok(GeographyLevel::MergedWard),
+            "la" => Ok(GeographyLevel::LocalAuthority),
+            "pcon" => Ok(GeographyLevel::ParliamentaryConstituency),
+            "region" | "r" => Ok(GeographyLevel::Region),
+            "country" => Ok(GeographyLevel::Country),
+            _ => Err(invalid_name("Geography level", format!("Unknown geography code {:?}", code))),
+        }
     }
-    return match code {
-        "QS" => { Ok("Quick Statistics") }
-        "KS" => { Ok("Key Statistics") }
-        "DC" => { Ok("Detailed Characteristics") }
-        "LC" => { Ok("Local Characteristics") }
-        "WD" => { Ok("Workday Population") }
-        "WP" => { Ok("Workplace Population Tables") }
-        "CT" => { Ok("Commissioned Tables") }
-        _ => {
-            Err(format!("Unknown table code {}", code))
+    pub fn code(&self) -> &'static str {
+        match self {
+            GeographyLevel::OutputArea => "oa",
+            GeographyLevel::LowerLayerSuperOutputArea => "lsoa",
+            GeographyLevel::MiddleLayerSuperOutputArea => "msoa",
+            GeographyLevel::Ward => "ward",
+            GeographyLevel::MergedWard => "merward",
+            GeographyLevel::LocalAuthority => "la",
+            GeographyLevel::ParliamentaryConstituency => "pcon",
+            GeographyLevel::Region => "region",
+            GeographyLevel::Country => "country",
         }
-    };
+    }
 }
 
-fn country_code_to_string(code: &str) -> Result<&str, String> {
-    if code.len() != 2 {
-        return Err(format!("Country Code is not the correct amount of characters {} ", code.len()));
+impl Display for GeographyLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeographyLevel::OutputArea => write!(f, "Output Area"),
+            GeographyLevel::LowerLayerSuperOutputArea => write!(f, "Lower Level Super Output Area"),
+            GeographyLevel::MiddleLayerSuperOutputArea => write!(f, "Middle Level Super Output Area"),
+            GeographyLevel::Ward => write!(f, "Ward"),
+            GeographyLevel::MergedWard => write!(f, "Merged Wards"),
+            GeographyLevel::LocalAuthority => write!(f, "Local Authority"),
+            GeographyLevel::ParliamentaryConstituency => write!(f, "Parliamentary Constituency"),
+            GeographyLevel::Region => write!(f, "Region"),
+            GeographyLevel::Country => write!(f, "Country"),
+        }
     }
-    return match code {
-        "EW" => { Ok("England & Wales") }
-        "WA" => { Ok("Wales") }
-        _ => {
-            Err(format!("Unknown country code: {}", code))
+}
+
+/// What a bulk file contains
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    /// The table itself, with one row per area and one column per cell
+    Data,
+    /// The labels for each column code
+    Description,
+    Metadata,
+}
+
+impl FileKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            FileKind::Data => "DATA",
+            FileKind::Description => "DESC",
+            FileKind::Metadata => "META",
         }
-    };
+    }
 }
 
+/// A decoded bulk file name, such as `KS101EWDATA.CSV` or `QS1101EWDESC0.CSV`
+///
+/// Converting back to a string gives the canonical name
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CensusFileName {
+    pub table_type: TableType,
+    /// Either three or four digits, such as 101 for `KS101EW`
+    pub table_number: u32,
+    pub country: Country,
+    pub kind: FileKind,
+    /// The digits after the kind, such as `0` for `DESC0`, which are kept as written
+    pub part: Option<String>,
+    /// Not present in every release, in which case it comes from the archive name
+    pub geography: Option<GeographyLevel>,
+}
 
-fn geography_code_to_string(code: &str) -> Result<&str, String> {
-    if code.len() != 1 && code.len() != 2 {
-        return Err(format!("Geography Code is not the correct amount of characters {}", code.len()));
+impl CensusFileName {
+    /// The table name used by NOMIS, such as `KS101EW`
+    pub fn table_name(&self) -> String {
+        format!("{}{}{}", self.table_type.code(), self.table_number, self.country.code())
     }
-    return match code {
-        "r" => { Ok("Region") }
-        "la" => { Ok("Merged Wards") }
-        "ls" => { Ok("Lower Level Super Output Area") }
-        _ => {
-            Ok("")
+    /// A human readable description, such as `Key Statistics - 101 - England & Wales - KS101EWDATA.CSV`
+    pub fn description(&self) -> String {
+        let mut description = format!("{} - {} - {}", self.table_type, self.table_number, self.country);
+        if let Some(geography) = &self.geography {
+            description += &format!(" - {}", geography);
         }
-    };
+        description + &format!(" - {}", self)
+    }
 }
 
-fn parse_filename(name: &str) -> Result<String, String> {
-    let name = name.split("/").last().unwrap();
-    if name.len() < 16 || name.len() > 20 {
-        return Err(format!("Name is not the correct amount of characters {} {}", name, name.len()));
+impl FromStr for CensusFileName {
+    type Err = ParsingError;
+
+    /// Parses a bulk file name, ignoring any directories before it
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.rsplit('/').next().unwrap_or_default();
+        let stem = name.strip_suffix(".CSV").or_else(|| name.strip_suffix(".csv"))
+            .ok_or_else(|| invalid_name("Extension", format!("{} is not a CSV file", name)))?;
+        let table_type = TableType::from_code(stem.get(0..2).ok_or_else(|| invalid_name("Table type", format!("{} is too short", name)))?)?;
+        let number_length = stem[2..].chars().take_while(|c| c.is_ascii_digit()).count();
+        if number_length != 3 && number_length != 4 {
+            return Err(invalid_name("Table number", format!("{} should have a three or four digit table number", name)));
+        }
+        let table_number = stem[2..2 + number_length].parse()?;
+        let rest = &stem[2 + number_length..];
+        let country = Country::from_code(rest.get(0..2).ok_or_else(|| invalid_name("Country", format!("{} has no country code", name)))?)?;
+        let rest = &rest[2..];
+        let kind = [FileKind::Data, FileKind::Description, FileKind::Metadata].iter()
+            .find(|kind| rest.starts_with(kind.code()))
+            .copied()
+            .ok_or_else(|| invalid_name("File kind", format!("{} is not a DATA, DESC or META file", name)))?;
+        let rest = &rest[kind.code().len()..];
+        let part_length = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+        let part = if part_length == 0 { None } else { Some(rest[..part_length].to_string()) };
+        let geography = match &rest[part_length..] {
+            "" => None,
+            geography => Some(GeographyLevel::from_code(geography)?),
+        };
+        Ok(CensusFileName { table_type, table_number, country, kind, part, geography })
     }
-    if !name.contains("DATA.CSV") {
-        return Err(format!("Invalid file"));
+}
+
+impl Display for CensusFileName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.table_name(), self.kind.code())?;
+        if let Some(part) = &self.part {
+            write!(f, "{}", part)?;
+        }
+        if let Some(geography) = &self.geography {
+            write!(f, "{}", geography.code())?;
+        }
+        write!(f, ".CSV")
     }
+}
 
-    let mut new_name = String::new();
-    new_name += table_type_code_to_string(&name[0..2])?;
-    new_name += " - ";
-    new_name += &name[2..6];
-    new_name += " - ";
-    new_name += country_code_to_string(&name[6..8])?;
-    new_name += " - ";
-    new_name += geography_code_to_string(&name[6..8])?;
-    new_name += " - ";
-    new_name += name;
-    Ok(new_name)
+fn invalid_name(part: &str, message: String) -> ParsingError {
+    ParsingError::new(ParsingErrorType::InvalidDataType(format!("Census file name {}", part)), Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CensusFileName, Country, FileKind, GeographyLevel, TableType};
+
+    #[test]
+    fn round_trips_canonical_names() {
+        for name in ["KS101EWDATA.CSV", "QS1101EWDATA.CSV", "QS1101EWDESC0.CSV", "LC2101EWMETA.CSV", "KS101EWDATAoa.CSV", "DC1104WADATAlsoa.CSV"] {
+            assert_eq!(name.parse::<CensusFileName>().unwrap().to_string(), name);
+        }
+    }
+
+    #[test]
+    fn decodes_each_part() {
+        let name: CensusFileName = "data/bulk/QS1101ENDESC0msoa.csv".parse().unwrap();
+        assert_eq!(name.table_type, TableType::QuickStatistics);
+        assert_eq!(name.table_number, 1101);
+        assert_eq!(name.country, Country::England);
+        assert_eq!(name.kind, FileKind::Description);
+        assert_eq!(name.part.as_deref(), Some("0"));
+        assert_eq!(name.geography, Some(GeographyLevel::MiddleLayerSuperOutputArea));
+        assert_eq!(name.table_name(), "QS1101EN");
+        assert_eq!(name.to_string(), "QS1101ENDESC0msoa.CSV");
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for name in ["KS101EWDATA.TXT", "XX101EWDATA.CSV", "KS10EWDATA.CSV", "KS101XXDATA.CSV", "KS101EWDUMP.CSV", "KS101EWDATAzz.CSV", "KS"] {
+            assert!(name.parse::<CensusFileName>().is_err(), "{} should be rejected", name);
+        }
+    }
 }
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use enum_map::{Enum, EnumMap};
+
+use serde::de::DeserializeOwned;
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+use crate::population_and_density_per_output_area::PreProcessingRecord;
+
+/// A NOMIS table, built one area at a time from the long format rows of a download
+///
+/// Only rows with the `Value` measure are used, so percentages are ignored
+/// Use `census_table!` for tables with a single count per cell, or implement this directly for anything more complicated
+pub trait CensusTable: Sized {
+    /// The NOMIS id, such as `NM_144_1`
+    const TABLE_ID: &'static str;
+    /// The cells of the table, parsed from `CELL_NAME` with `serde_plain`
+    type Cell: DeserializeOwned;
+    /// Parsed from `OBS_VALUE`, where a suppressed (empty) value becomes the default
+    type Value: FromStr + Default;
+
+    /// An empty record for a single area
+    fn new(geography_code: String, geography_type: String) -> Self;
+    /// The area the record is for, which parsed tables are keyed by
+    fn geography_code(&self) -> &str;
+    /// Adds the value of a single cell, where `record` is the row it came from
+    fn add(&mut self, record: &PreProcessingRecord, cell: Self::Cell, value: Self::Value) -> Result<(), ParsingError>;
+    /// Called once every row of the area has been added, such as to check the cells add up to the total
+    fn validate(&self) -> Result<(), ParsingError> {
+        Ok(())
+    }
+}
+
+/// Builds the record for a single area from all of its rows
+pub fn build_record<T: CensusTable>(records: Vec<PreProcessingRecord>) -> Result<T, ParsingError> {
+    if records.is_empty() {
+        return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Array is empty")), Some(format!("Need at least one record, to build a {} record", T::TABLE_ID))));
+    }
+    let geography_code = String::from(&records[0].geography_name);
+    let geography_type = String::from(&records[0].geography_type);
+    let mut table = T::new(geography_code.clone(), geography_type.clone());
+    for record in records {
+        if record.geography_name != geography_code {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from(&record.geography_name)), Some(format!("Mis matching geography codes: {} and {}", geography_code, record.geography_name))));
+        }
+        if record.geography_type != geography_type {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from(&record.geography_type)), Some(format!("Mis matching geography type: {} and {}", geography_type, record.geography_type))));
+        }
+        if record.measures_name != "Value" {
+            continue;
+        }
+        let cell: T::Cell = serde_plain::from_str(&record.cell_name)?;
+        let value = if record.obs_value.is_empty() {
+            T::Value::default()
+        } else {
+            record.obs_value.parse().map_err(|_| ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Value")), Some(format!("Invalid value {:?} for {} in {}", record.obs_value, record.cell_name, geography_code))))?
+        };
+        table.add(&record, cell, value)?;
+    }
+    table.validate()?;
+    Ok(table)
+}
+
+/// Defines the record for a table with one value per cell, only using the `Total` rural urban classification
+///
+/// ```ignore
+/// census_table! {
+///     /// Age by single year (QS103EW)
+///     pub struct AgeRecord("NM_503_1", Age, u32);
+/// }
+/// ```
+///
+/// This gives a struct with `geography_code`, `geography_type` and a `values: EnumMap<Age, u32>`, where `Age` is an `enum_map::Enum` that can be deserialized from the `CELL_NAME`s
+/// A function taking the record can be given as `validate = check_ages` after the value type, to be used as `CensusTable::validate`
+macro_rules! census_table {
+    ($(#[$meta:meta])* $visibility:vis struct $name:ident($table_id:expr, $cell:ty, $value:ty $(, validate = $validate:path)?);) => {
+        $(#[$meta])*
+        #[derive(Debug, PartialEq)]
+        $visibility struct $name {
+            pub geography_code: String,
+            pub geography_type: String,
+            pub values: enum_map::EnumMap<$cell, $value>,
+        }
+
+        impl $crate::census_table::CensusTable for $name {
+            const TABLE_ID: &'static str = $table_id;
+            type Cell = $cell;
+            type Value = $value;
+
+            fn new(geography_code: String, geography_type: String) -> Self {
+                $name { geography_code, geography_type, values: enum_map::EnumMap::default() }
+            }
+            fn geography_code(&self) -> &str {
+                &self.geography_code
+            }
+            fn add(&mut self, record: &$crate::population_and_density_per_output_area::PreProcessingRecord, cell: $cell, value: $value) -> Result<(), $crate::parsing_error::ParsingError> {
+                if record.rural_urban_name == "Total" {
+                    self.values[cell] = value;
+                }
+                Ok(())
+            }
+            $(
+            fn validate(&self) -> Result<(), $crate::parsing_error::ParsingError> {
+                $validate(self)
+            }
+            )?
+        }
+    };
+}
+
+pub(crate) use census_table;
+
+/// Pairs up the records of two tables for every area in both, such as a `PopulationRecord` with its `ModeShare`
+pub fn join_by_area<'a, A, B>(left: &'a HashMap<String, A>, right: &'a HashMap<String, B>) -> HashMap<&'a str, (&'a A, &'a B)> {
+    left.iter().filter_map(|(code, left)| Some((code.as_str(), (left, right.get(code)?)))).collect()
+}
+
+/// Checks each cell with children adds up to the sum of its children, such as "All categories" being the sum of every top level cell
+pub fn check_totals<C: Enum<u32> + Enum<Option<u32>> + Copy + Debug>(geography_code: &str, values: &EnumMap<C, u32>, parent: fn(C) -> Option<C>) -> Result<(), ParsingError> {
+    let mut sums: EnumMap<C, Option<u32>> = EnumMap::default();
+    for (cell, value) in values.iter() {
+        if let Some(parent) = parent(cell) {
+            sums[parent] = Some(sums[parent].unwrap_or(0) + value);
+        }
+    }
+    for (cell, sum) in sums.iter() {
+        if let Some(sum) = sum {
+            if *sum != values[cell] {
+                return Err(ParsingError::new(ParsingErrorType::InvalidDataType(format!("{:?}", cell)), Some(format!("The parts of {:?} in {} add up to {}, but its total is {}", cell, geography_code, sum, values[cell]))));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use crate::nomis_download::DataFetcher;
+    use crate::population_and_density_per_output_area::PreProcessingRecord;
+    use crate::progress::NoProgress;
+
+    use super::{build_record, CensusTable};
+
+    #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Enum)]
+    enum Sex {
+        #[serde(alias = "All persons")]
+        All,
+        #[serde(alias = "Males")]
+        Male,
+        #[serde(alias = "Females")]
+        Female,
+    }
+
+    census_table! {
+        /// A made up table, to check the macro
+        struct SexRecord("NM_0_1", Sex, u32);
+    }
+
+    fn row(geography: &str, rural_urban: &str, cell: &str, measures: &str, value: &str) -> PreProcessingRecord {
+        PreProcessingRecord {
+            geography_name: geography.to_string(),
+            geography_type: String::from("2011 output areas"),
+            rural_urban_name: rural_urban.to_string(),
+            cell_name: cell.to_string(),
+            measures_name: measures.to_string(),
+            obs_value: value.to_string(),
+            obs_status: String::from("A"),
+            record_offset: 0,
+            record_count: 0,
+        }
+    }
+
+    /// The `Value` and `Percent` rows of a NOMIS download for one area, with every cell in the `Total` rural urban classification
+    pub(crate) fn cell_rows(geography: &str, cells: &[(&str, u32)]) -> String {
+        cells.iter().map(|(name, value)| {
+            format!("{},2011 output areas,Total,\"{}\",Value,{},A,0,0\n{},2011 output areas,Total,\"{}\",Percent,50.0,A,0,0\n", geography, name, value, geography, name)
+        }).collect()
+    }
+
+    /// Parses the rows of a NOMIS CSV download, after adding its header
+    pub(crate) fn parse_rows<T: CensusTable>(rows: &str) -> HashMap<String, T> {
+        let data = format!("GEOGRAPHY_NAME,GEOGRAPHY_TYPE,RURAL_URBAN_NAME,CELL_NAME,MEASURES_NAME,OBS_VALUE,OBS_STATUS,RECORD_OFFSET,RECORD_COUNT\n{}", rows);
+        DataFetcher::parse_table(csv::Reader::from_reader(data.as_bytes()), &NoProgress).unwrap()
+    }
+
+    #[test]
+    fn builds_records_from_value_rows() {
+        let record: SexRecord = build_record(vec![
+            row("E00000001", "Total", "All persons", "Value", "10"),
+            row("E00000001", "Total", "All persons", "Percent", "100.0"),
+            row("E00000001", "Total", "Males", "Value", "4"),
+            row("E00000001", "Urban (total)", "Males", "Value", "3"),
+            row("E00000001", "Total", "Females", "Value", ""),
+        ]).unwrap();
+        assert_eq!(record.geography_code, "E00000001");
+        assert_eq!((record.values[Sex::All], record.values[Sex::Male], record.values[Sex::Female]), (10, 4, 0));
+    }
+
+    #[test]
+    fn rejects_unknown_cells_and_mixed_areas() {
+        assert!(build_record::<SexRecord>(vec![row("E00000001", "Total", "Unknown", "Value", "1")]).is_err());
+        assert!(build_record::<SexRecord>(vec![row("E00000001", "Total", "Males", "Value", "1"), row("E00000002", "Total", "Males", "Value", "1")]).is_err());
+        assert!(build_record::<SexRecord>(Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+const CHECKPOINT_FILENAME: &str = "checkpoint.json";
+/// Numbers the temporary files of `write_atomically`, so concurrent writes to the same path never share one
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Records which pages of a table download have been saved to disk
+///
+/// Each page is stored in its own file next to the checkpoint, so a restarted download only has to request the missing pages
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DownloadCheckpoint {
+    pub table_id: String,
+    pub geography: String,
+    /// The request for the whole table, without any paging, so checkpoints for different cells, measures or columns aren't mixed up
+    #[serde(default)]
+    pub query: String,
+    pub page_size: usize,
+    /// The number of records being downloaded, which a record count override can make smaller than the table
+    #[serde(default)]
+    pub number_of_records: usize,
+    pub page_count: usize,
+    pub completed_pages: BTreeSet<usize>,
+    #[serde(skip)]
+    directory: PathBuf,
+}
+
+impl DownloadCheckpoint {
+    /// Loads the checkpoint from the given directory, or starts a new one if it doesn't exist
+    ///
+    /// If the existing checkpoint is for a different download, including one with a different record count, its pages can't be reused, so they are deleted and the download starts again
+    pub fn load_or_create(directory: &str, table_id: &str, geography: &str, query: &str, page_size: usize, number_of_records: usize, page_count: usize) -> Result<DownloadCheckpoint, ParsingError> {
+        let directory = PathBuf::from(directory);
+        fs::create_dir_all(&directory)?;
+        let mut checkpoint = DownloadCheckpoint {
+            table_id: table_id.to_string(),
+            geography: geography.to_string(),
+            query: query.to_string(),
+            page_size,
+            number_of_records,
+            page_count,
+            completed_pages: BTreeSet::new(),
+            directory,
+        };
+        let manifest = checkpoint.directory.join(CHECKPOINT_FILENAME);
+        if manifest.exists() {
+            let saved: DownloadCheckpoint = serde_json::from_reader(File::open(&manifest)?)?;
+            if saved.is_same_download(&checkpoint) {
+                info!("Resuming download of {} with {}/{} pages completed", table_id, saved.completed_pages.len(), page_count);
+                checkpoint.completed_pages = saved.completed_pages;
+                return Ok(checkpoint);
+            }
+            warn!("Restarting the download in {:?}, as its checkpoint is for {} records of {} in {} pages of {}, not {} records of {} in {} pages of {}", checkpoint.directory, saved.number_of_records, saved.query, saved.page_count, saved.page_size, number_of_records, query, page_count, page_size);
+            for index in &saved.completed_pages {
+                let page = checkpoint.page_path(*index);
+                if page.exists() {
+                    fs::remove_file(page)?;
+                }
+            }
+        }
+        checkpoint.save()?;
+        Ok(checkpoint)
+    }
+    /// Whether the pages of the other checkpoint hold the same rows as this one
+    fn is_same_download(&self, other: &DownloadCheckpoint) -> bool {
+        self.table_id == other.table_id
+            && self.geography == other.geography
+            && self.query == other.query
+            && self.page_size == other.page_size
+            && self.number_of_records == other.number_of_records
+            && self.page_count == other.page_count
+    }
+    /// Returns the indexes of the pages that still need downloading
+    pub fn remaining_pages(&self) -> Vec<usize> {
+        (0..self.page_count).filter(|index| !self.completed_pages.contains(index)).collect()
+    }
+    pub fn is_complete(&self) -> bool {
+        self.remaining_pages().is_empty()
+    }
+    /// Writes the page to disk, and then records it as finished in the checkpoint
+    pub fn save_page(&mut self, index: usize, data: &str) -> Result<(), ParsingError> {
+        write_atomically(&self.page_path(index), data.as_bytes())?;
+        self.completed_pages.insert(index);
+        self.save()
+    }
+    /// Joins all the saved pages together in `RecordOffset` order
+    pub fn assemble(&self) -> Result<String, ParsingError> {
+        if !self.is_complete() {
+            return Err(ParsingError::new(ParsingErrorType::MissingKey, Some(format!("Download of {} is missing pages {:?}", self.table_id, self.remaining_pages()))));
+        }
+        let mut data = String::new();
+        for index in 0..self.page_count {
+            data.push_str(&fs::read_to_string(self.page_path(index))?);
+        }
+        Ok(data)
+    }
+    fn page_path(&self, index: usize) -> PathBuf {
+        self.directory.join(format!("page_{}.csv", index))
+    }
+    fn save(&self) -> Result<(), ParsingError> {
+        let data = serde_json::to_string_pretty(self)?;
+        write_atomically(&self.directory.join(CHECKPOINT_FILENAME), data.as_bytes())
+    }
+}
+
+/// Writes to a temporary file first, so a crash never leaves a half written page or checkpoint behind
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<(), ParsingError> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let temp_path = path.with_file_name(format!("{}.{}.{}.tmp", file_name, std::process::id(), TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+    let mut file = File::create(&temp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(temp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::DownloadCheckpoint;
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("download_checkpoint_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn load(directory: &Path, query: &str, number_of_records: usize, page_count: usize) -> DownloadCheckpoint {
+        DownloadCheckpoint::load_or_create(directory.to_str().unwrap(), "NM_144_1", "2092957699TYPE299", query, 2, number_of_records, page_count).unwrap()
+    }
+
+    #[test]
+    fn resumes_and_assembles_pages_in_order() {
+        let directory = temp_directory("resume");
+        let mut checkpoint = load(&directory, "NM_144_1.data.csv", 5, 3);
+        checkpoint.save_page(2, "e\n").unwrap();
+        checkpoint.save_page(0, "a\nb\n").unwrap();
+        assert!(checkpoint.assemble().is_err());
+
+        let mut checkpoint = load(&directory, "NM_144_1.data.csv", 5, 3);
+        assert_eq!(checkpoint.remaining_pages(), vec![1]);
+        checkpoint.save_page(1, "c\nd\n").unwrap();
+        assert_eq!(checkpoint.assemble().unwrap(), "a\nb\nc\nd\ne\n");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn restarts_checkpoints_for_other_downloads() {
+        let directory = temp_directory("restart");
+        let mut checkpoint = load(&directory, "NM_144_1.data.csv", 5, 3);
+        checkpoint.save_page(0, "a\nb\n").unwrap();
+        checkpoint.save_page(1, "c\nd\n").unwrap();
+        assert_eq!(load(&directory, "NM_144_1.data.csv", 5, 3).remaining_pages(), vec![2]);
+
+        assert_eq!(load(&directory, "NM_144_1.data.csv", 3, 2).remaining_pages(), vec![0, 1]);
+        assert!(!directory.join("page_1.csv").exists());
+        load(&directory, "NM_144_1.data.csv", 3, 2).save_page(0, "a\nb\n").unwrap();
+        assert_eq!(load(&directory, "NM_144_1.data.csv?cell=0", 3, 2).remaining_pages(), vec![0, 1]);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::download_checkpoint::write_atomically;
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+/// Appended to the name of the data file, to give the name of its manifest
+const MANIFEST_EXTENSION: &str = ".manifest.json";
+
+/// Where a downloaded table came from, written next to the data file so results can be traced back to the exact request
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DownloadManifest {
+    pub table_id: String,
+    /// The full request for each part of the geography selection, without the paging parameters or API key
+    pub queries: Vec<String>,
+    pub geography: String,
+    /// Seconds since the Unix epoch when the download finished
+    pub fetched_at: u64,
+    pub page_size: usize,
+    /// The number of pages requested, over every part of the geography selection
+    pub pages: usize,
+    /// The number of data rows, not including the column headings
+    pub row_count: usize,
+    /// The hex SHA-256 of the data file
+    pub sha256: String,
+    /// The NOMIS metadata text for the table, if it was in the catalogue
+    pub metadata: Option<String>,
+}
+
+impl DownloadManifest {
+    pub fn new(table_id: &str, queries: Vec<String>, geography: &str, page_size: usize, pages: usize, data: &str, metadata: Option<String>) -> Result<DownloadManifest, ParsingError> {
+        let fetched_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or_default();
+        Ok(DownloadManifest {
+            table_id: table_id.to_string(),
+            queries,
+            geography: geography.to_string(),
+            fetched_at,
+            page_size,
+            pages,
+            row_count: count_rows(data)?,
+            sha256: hash(data.as_bytes()),
+            metadata,
+        })
+    }
+    /// The path of the manifest for the given data file, such as `NM_144_1.csv.manifest.json`
+    pub fn path_for(data_path: &Path) -> PathBuf {
+        let mut path = data_path.as_os_str().to_owned();
+        path.push(MANIFEST_EXTENSION);
+        PathBuf::from(path)
+    }
+    /// Writes the data file, and then its manifest
+    pub fn write(&self, data_path: &Path, data: &str) -> Result<(), ParsingError> {
+        write_atomically(data_path, data.as_bytes())?;
+        write_atomically(&DownloadManifest::path_for(data_path), serde_json::to_string_pretty(self)?.as_bytes())
+    }
+    pub fn load(data_path: &Path) -> Result<DownloadManifest, ParsingError> {
+        Ok(serde_json::from_reader(File::open(DownloadManifest::path_for(data_path))?)?)
+    }
+    /// Re-hashes the data file, and checks it still matches this manifest
+    pub fn verify(&self, data_path: &Path) -> Result<(), ParsingError> {
+        let data = fs::read_to_string(data_path)?;
+        let sha256 = hash(data.as_bytes());
+        if sha256 != self.sha256 {
+            return Err(verification_failed(format!("{:?} has SHA-256 {}, but its manifest has {}", data_path, sha256, self.sha256)));
+        }
+        let row_count = count_rows(&data)?;
+        if row_count != self.row_count {
+            return Err(verification_failed(format!("{:?} has {} rows, but its manifest has {}", data_path, row_count, self.row_count)));
+        }
+        Ok(())
+    }
+}
+
+/// Loads the manifest for the given data file, and checks the file against it
+pub fn verify(data_path: &Path) -> Result<DownloadManifest, ParsingError> {
+    let manifest = DownloadManifest::load(data_path)?;
+    manifest.verify(data_path)?;
+    info!("Verified {:?} against its manifest", data_path);
+    Ok(manifest)
+}
+
+/// Verifies every data file with a manifest in the directory, returning the result for each data file
+pub fn verify_directory(directory: &str) -> Vec<(PathBuf, Result<DownloadManifest, ParsingError>)> {
+    WalkDir::new(directory).sort_by_file_name().into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?;
+            let data_name = name.strip_suffix(MANIFEST_EXTENSION)?;
+            Some(entry.path().with_file_name(data_name))
+        })
+        .map(|data_path| {
+            let result = verify(&data_path);
+            (data_path, result)
+        })
+        .collect()
+}
+
+fn count_rows(data: &str) -> Result<usize, ParsingError> {
+    let mut count = 0;
+    for record in csv::Reader::from_reader(data.as_bytes()).records() {
+        record?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn verification_failed(message: String) -> ParsingError {
+    ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Manifest")), Some(message))
+}
@@ -0,0 +1,289 @@
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+
+use serde_json::Value;
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+use crate::sdmx_codelist::{parse_codelist, CodelistEntry};
+
+/// The NOMIS code for England
+pub const ENGLAND: &str = "2092957699";
+/// The NOMIS code for Wales
+pub const WALES: &str = "2092957700";
+/// The NOMIS code for England and Wales
+pub const ENGLAND_AND_WALES: &str = "2092957703";
+
+/// The types of geography the 2011 census tables are published at, identified by their NOMIS `TYPE` code
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GeographyType {
+    Country,
+    Region,
+    LocalAuthority,
+    MiddleLayerSuperOutputArea,
+    LowerLayerSuperOutputArea,
+    OutputArea,
+    Other(u32),
+}
+
+impl GeographyType {
+    pub fn from_type_code(code: u32) -> GeographyType {
+        match code {
+            499 => GeographyType::Country,
+            480 => GeographyType::Region,
+            464 => GeographyType::LocalAuthority,
+            297 => GeographyType::MiddleLayerSuperOutputArea,
+            298 => GeographyType::LowerLayerSuperOutputArea,
+            299 => GeographyType::OutputArea,
+            other => GeographyType::Other(other),
+        }
+    }
+    pub fn type_code(&self) -> u32 {
+        match self {
+            GeographyType::Country => 499,
+            GeographyType::Region => 480,
+            GeographyType::LocalAuthority => 464,
+            GeographyType::MiddleLayerSuperOutputArea => 297,
+            GeographyType::LowerLayerSuperOutputArea => 298,
+            GeographyType::OutputArea => 299,
+            GeographyType::Other(code) => *code,
+        }
+    }
+    /// Parses a type code in either the `TYPE299` or `299` form
+    pub fn from_type_string(code: &str) -> Result<GeographyType, ParsingError> {
+        let code = code.trim_start_matches("TYPE");
+        Ok(GeographyType::from_type_code(code.parse()?))
+    }
+    /// The code that selects every geography of this type within the given parent, such as `2092957699TYPE299` for all output areas in England
+    pub fn within(&self, parent_code: &str) -> String {
+        format!("{}TYPE{}", parent_code, self.type_code())
+    }
+}
+
+impl Display for GeographyType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeographyType::Country => write!(f, "countries"),
+            GeographyType::Region => write!(f, "regions"),
+            GeographyType::LocalAuthority => write!(f, "local authorities"),
+            GeographyType::MiddleLayerSuperOutputArea => write!(f, "2011 super output areas - middle layer"),
+            GeographyType::LowerLayerSuperOutputArea => write!(f, "2011 super output areas - lower layer"),
+            GeographyType::OutputArea => write!(f, "2011 output areas"),
+            GeographyType::Other(code) => write!(f, "TYPE{}", code),
+        }
+    }
+}
+
+/// A single geography from a `geography.def.sdmx.json` response
+///
+/// This is either an individual area (such as England), or every area of one type within a parent (such as all output areas in England)
+#[derive(Clone, Debug)]
+pub struct Geography {
+    /// The code NOMIS uses in the `geography` parameter, such as `2092957699` or `2092957699TYPE299`
+    pub code: String,
+    pub name: String,
+    pub geography_type: Option<GeographyType>,
+    /// The human readable name of the type, such as "2011 output areas"
+    pub type_name: Option<String>,
+    /// The ONS code, such as `E92000001`
+    pub gss_code: Option<String>,
+    pub parent_code: Option<String>,
+}
+
+impl Geography {
+    /// Whether this selects every area of a type, rather than a single area
+    pub fn is_type_selection(&self) -> bool {
+        self.code.contains("TYPE")
+    }
+}
+
+impl TryFrom<CodelistEntry> for Geography {
+    type Error = ParsingError;
+
+    fn try_from(entry: CodelistEntry) -> Result<Self, Self::Error> {
+        let type_code = entry.annotations.get("TypeCode").cloned().or_else(|| entry.value.split_once("TYPE").map(|(_, code)| code.to_string()));
+        let geography_type = match type_code {
+            Some(code) => Some(GeographyType::from_type_string(&code)?),
+            None => None,
+        };
+        Ok(Geography {
+            code: entry.value,
+            name: entry.description,
+            geography_type,
+            type_name: entry.annotations.get("TypeName").cloned(),
+            gss_code: entry.annotations.get("GeogCode").cloned(),
+            parent_code: entry.parent_code,
+        })
+    }
+}
+
+/// The geographies a table is available for, with the parent child relationships between them
+#[derive(Clone, Debug, Default)]
+pub struct GeographyHierarchy {
+    pub geographies: Vec<Geography>,
+}
+
+impl GeographyHierarchy {
+    pub fn from_json(json: &Value) -> Result<GeographyHierarchy, ParsingError> {
+        let geographies = parse_codelist(json)?.into_iter().map(Geography::try_from).collect::<Result<Vec<Geography>, ParsingError>>()?;
+        Ok(GeographyHierarchy { geographies })
+    }
+    pub fn get(&self, code: &str) -> Option<&Geography> {
+        self.geographies.iter().find(|geography| geography.code == code)
+    }
+    /// Finds the individual area with the given name (ignoring case), such as "England"
+    pub fn get_by_name(&self, name: &str) -> Option<&Geography> {
+        self.geographies.iter().find(|geography| !geography.is_type_selection() && geography.name.eq_ignore_ascii_case(name))
+    }
+    pub fn parent(&self, code: &str) -> Option<&Geography> {
+        self.get(code)?.parent_code.as_ref().and_then(|parent| self.get(parent))
+    }
+    pub fn children(&self, code: &str) -> Vec<&Geography> {
+        self.geographies.iter().filter(|geography| geography.parent_code.as_deref() == Some(code)).collect()
+    }
+    /// The geography types available within the given area
+    pub fn types_within(&self, code: &str) -> Vec<GeographyType> {
+        self.children(code).iter().filter_map(|geography| geography.geography_type).collect()
+    }
+    /// Finds the code that selects every area of the given type within the named area, such as all output areas in "England"
+    pub fn select(&self, parent_name: &str, geography_type: GeographyType) -> Result<&Geography, ParsingError> {
+        let parent = self.get_by_name(parent_name).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(format!("No geography named {}", parent_name))))?;
+        self.children(&parent.code)
+            .into_iter()
+            .find(|geography| geography.geography_type == Some(geography_type))
+            .ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(format!("{} has no {}", parent_name, geography_type))))
+    }
+}
+
+/// The longest `geography` parameter sent in a single request once URL encoded, longer selections are split over several requests
+const MAX_GEOGRAPHY_PARAMETER_LENGTH: usize = 4000;
+
+/// The length of a query parameter value once form URL encoded, where every comma becomes `%2C`
+fn encoded_length(value: &str) -> usize {
+    value.bytes().map(|byte| if byte.is_ascii_alphanumeric() || b"*-._ ".contains(&byte) { 1 } else { 3 }).sum()
+}
+
+/// Which geographies to download a table for
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeographySelector {
+    /// Every area of the given type within a parent, such as all output areas in England
+    Type { parent_code: String, geography_type: GeographyType },
+    /// An explicit list of NOMIS geography codes
+    Codes(Vec<String>),
+    /// Inclusive ranges of numeric NOMIS geography codes, in the compressed `1237321209...1237321232` form
+    Ranges(Vec<(u64, u64)>),
+}
+
+impl GeographySelector {
+    pub fn england_output_areas() -> GeographySelector {
+        GeographySelector::Type { parent_code: ENGLAND.to_string(), geography_type: GeographyType::OutputArea }
+    }
+    /// Parses a compressed `geography` parameter, such as `1237321209...1237321232,1237326984`
+    pub fn from_ranges(parameter: &str) -> Result<GeographySelector, ParsingError> {
+        let mut ranges = Vec::new();
+        for item in parameter.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.split_once("...") {
+                Some((start, end)) => {
+                    let (start, end): (u64, u64) = (start.parse()?, end.parse()?);
+                    if start > end {
+                        return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Geography range")), Some(format!("Range {} starts after it ends", item))));
+                    }
+                    ranges.push((start, end));
+                }
+                None => {
+                    let code = item.parse()?;
+                    ranges.push((code, code));
+                }
+            }
+        }
+        Ok(GeographySelector::Ranges(ranges))
+    }
+    /// Compresses runs of consecutive codes into ranges, keeping the given order
+    pub fn from_numeric_codes(codes: &[u64]) -> GeographySelector {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for &code in codes {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == code => *end = code,
+                _ => ranges.push((code, code)),
+            }
+        }
+        GeographySelector::Ranges(ranges)
+    }
+    /// Builds the `geography` parameter for each request needed to cover this selection
+    pub fn to_parameters(&self) -> Vec<String> {
+        let items: Vec<String> = match self {
+            GeographySelector::Type { parent_code, geography_type } => return vec![geography_type.within(parent_code)],
+            GeographySelector::Codes(codes) => codes.clone(),
+            GeographySelector::Ranges(ranges) => ranges.iter().map(|(start, end)| {
+                if start == end { start.to_string() } else { format!("{}...{}", start, end) }
+            }).collect(),
+        };
+        let mut parameters = Vec::new();
+        let mut current = String::new();
+        for item in items {
+            if !current.is_empty() && encoded_length(&current) + encoded_length(",") + encoded_length(&item) > MAX_GEOGRAPHY_PARAMETER_LENGTH {
+                parameters.push(current);
+                current = String::new();
+            }
+            if !current.is_empty() {
+                current.push(',');
+            }
+            current.push_str(&item);
+        }
+        if !current.is_empty() {
+            parameters.push(current);
+        }
+        parameters
+    }
+}
+
+impl Display for GeographySelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_parameters().join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nomis_query::NomisQuery;
+
+    use super::{encoded_length, GeographySelector, MAX_GEOGRAPHY_PARAMETER_LENGTH};
+
+    #[test]
+    fn parses_compressed_ranges() {
+        let selector = GeographySelector::from_ranges("1237321209...1237321232, 1237326984,1237326985,").unwrap();
+        assert_eq!(selector, GeographySelector::Ranges(vec![(1237321209, 1237321232), (1237326984, 1237326984), (1237326985, 1237326985)]));
+        assert_eq!(selector.to_parameters(), vec!["1237321209...1237321232,1237326984,1237326985"]);
+        assert_eq!(GeographySelector::from_numeric_codes(&[1237326984, 1237326985, 1237321209]), GeographySelector::Ranges(vec![(1237326984, 1237326985), (1237321209, 1237321209)]));
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(GeographySelector::from_ranges("1237321232...1237321209").is_err());
+        assert!(GeographySelector::from_ranges("1237321209..1237321232").is_err());
+        assert!(GeographySelector::from_ranges("E00062207").is_err());
+    }
+
+    #[test]
+    fn splits_long_selections_by_encoded_length() {
+        let selection = include_str!("../data/fixtures/output_area_selection.txt").trim();
+        let selector = GeographySelector::from_ranges(selection).unwrap();
+        let parameters = selector.to_parameters();
+        assert!(parameters.len() > 1);
+        assert_eq!(parameters.join(","), selection);
+        for parameter in &parameters {
+            assert!(encoded_length(parameter) <= MAX_GEOGRAPHY_PARAMETER_LENGTH);
+            let url = NomisQuery::table("NM_144_1").with_geography(parameter).to_url("https://www.nomisweb.co.uk/api/v01/").unwrap();
+            assert_eq!(url.query().unwrap().len(), "geography=".len() + encoded_length(parameter));
+        }
+    }
+
+    #[test]
+    fn splits_code_lists() {
+        let codes: Vec<String> = (0..1000).map(|index| format!("E{:08}", index)).collect();
+        let parameters = GeographySelector::Codes(codes.clone()).to_parameters();
+        assert!(parameters.len() > 1);
+        assert!(parameters.iter().all(|parameter| encoded_length(parameter) <= MAX_GEOGRAPHY_PARAMETER_LENGTH));
+        assert_eq!(parameters.join(","), codes.join(","));
+        assert_eq!(GeographySelector::Codes(vec![String::from("E00062207")]).to_parameters(), vec!["E00062207"]);
+    }
+}
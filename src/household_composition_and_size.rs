@@ -0,0 +1,227 @@
+use enum_map::EnumMap;
+use serde::Deserialize;
+
+use crate::census_table::{census_table, check_totals};
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+use crate::population_and_density_per_output_area::{AreaClassification, PersonType, PopulationRecord};
+
+/// The cells of QS112EW, which count the people living in each type of household
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Enum)]
+pub enum HouseholdComposition {
+    #[serde(alias = "All categories: Household composition")]
+    All,
+    #[serde(alias = "One person household")]
+    OnePerson,
+    #[serde(alias = "One person household: Aged 65 and over")]
+    OnePersonAged65AndOver,
+    #[serde(alias = "One person household: Other")]
+    OnePersonOther,
+    #[serde(alias = "One family only")]
+    OneFamily,
+    #[serde(alias = "One family only: All aged 65 and over")]
+    OneFamilyAged65AndOver,
+    #[serde(alias = "One family only: Married or same-sex civil partnership couple")]
+    MarriedCouple,
+    #[serde(alias = "One family only: Married or same-sex civil partnership couple: No children")]
+    MarriedCoupleNoChildren,
+    #[serde(alias = "One family only: Married or same-sex civil partnership couple: Dependent children")]
+    MarriedCoupleDependentChildren,
+    #[serde(alias = "One family only: Married or same-sex civil partnership couple: All children non-dependent")]
+    MarriedCoupleNonDependentChildren,
+    #[serde(alias = "One family only: Cohabiting couple")]
+    CohabitingCouple,
+    #[serde(alias = "One family only: Cohabiting couple: No children")]
+    CohabitingCoupleNoChildren,
+    #[serde(alias = "One family only: Cohabiting couple: Dependent children")]
+    CohabitingCoupleDependentChildren,
+    #[serde(alias = "One family only: Cohabiting couple: All children non-dependent")]
+    CohabitingCoupleNonDependentChildren,
+    #[serde(alias = "One family only: Lone parent")]
+    LoneParent,
+    #[serde(alias = "One family only: Lone parent: Dependent children")]
+    LoneParentDependentChildren,
+    #[serde(alias = "One family only: Lone parent: All children non-dependent")]
+    LoneParentNonDependentChildren,
+    #[serde(alias = "Other household types")]
+    Other,
+    #[serde(alias = "Other household types: With dependent children")]
+    OtherDependentChildren,
+    #[serde(alias = "Other household types: All full-time students")]
+    OtherStudents,
+    #[serde(alias = "Other household types: All aged 65 and over")]
+    OtherAged65AndOver,
+    #[serde(alias = "Other household types: Other")]
+    OtherOther,
+}
+
+impl HouseholdComposition {
+    /// The cell one level up, such as `OneFamily` for `LoneParent`
+    pub fn parent(self) -> Option<HouseholdComposition> {
+        use HouseholdComposition::*;
+        match self {
+            All => None,
+            OnePerson | OneFamily | Other => Some(All),
+            OnePersonAged65AndOver | OnePersonOther => Some(OnePerson),
+            OneFamilyAged65AndOver | MarriedCouple | CohabitingCouple | LoneParent => Some(OneFamily),
+            MarriedCoupleNoChildren | MarriedCoupleDependentChildren | MarriedCoupleNonDependentChildren => Some(MarriedCouple),
+            CohabitingCoupleNoChildren | CohabitingCoupleDependentChildren | CohabitingCoupleNonDependentChildren => Some(CohabitingCouple),
+            LoneParentDependentChildren | LoneParentNonDependentChildren => Some(LoneParent),
+            OtherDependentChildren | OtherStudents | OtherAged65AndOver | OtherOther => Some(Other),
+        }
+    }
+}
+
+/// The cells of QS406EW, which count households by the number of people in them
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Enum)]
+pub enum HouseholdSize {
+    #[serde(alias = "All categories: Household size")]
+    All,
+    #[serde(alias = "1 person in household")]
+    One,
+    #[serde(alias = "2 people in household")]
+    Two,
+    #[serde(alias = "3 people in household")]
+    Three,
+    #[serde(alias = "4 people in household")]
+    Four,
+    #[serde(alias = "5 people in household")]
+    Five,
+    #[serde(alias = "6 people in household")]
+    Six,
+    #[serde(alias = "7 people in household")]
+    Seven,
+    #[serde(alias = "8 or more people in household")]
+    EightOrMore,
+}
+
+impl HouseholdSize {
+    pub fn parent(self) -> Option<HouseholdSize> {
+        match self {
+            HouseholdSize::All => None,
+            _ => Some(HouseholdSize::All),
+        }
+    }
+}
+
+census_table! {
+    /// The people in households of a single area by household composition, from QS112EW (`NM_512_1`)
+    pub struct HouseholdCompositionRecord("NM_512_1", HouseholdComposition, u32, validate = check_composition_totals);
+}
+
+census_table! {
+    /// The households of a single area by household size, from QS406EW (`NM_538_1`)
+    ///
+    /// `values[HouseholdSize::All]` is the number of households
+    pub struct HouseholdSizeRecord("NM_538_1", HouseholdSize, u32, validate = check_size_totals);
+}
+
+fn check_composition_totals(record: &HouseholdCompositionRecord) -> Result<(), ParsingError> {
+    check_totals(&record.geography_code, &record.values, HouseholdComposition::parent)
+}
+
+fn check_size_totals(record: &HouseholdSizeRecord) -> Result<(), ParsingError> {
+    check_totals(&record.geography_code, &record.values, HouseholdSize::parent)
+}
+
+/// The share of each of the most detailed cells, where `is_leaf` picks those cells out of the subtotals
+fn distribution<C: enum_map::Enum<u32> + enum_map::Enum<f64> + Copy>(values: &EnumMap<C, u32>, total: u32, is_leaf: impl Fn(C) -> bool) -> EnumMap<C, f64> {
+    let mut shares = EnumMap::default();
+    if total == 0 {
+        return shares;
+    }
+    for (cell, value) in values.iter() {
+        if is_leaf(cell) {
+            shares[cell] = *value as f64 / total as f64;
+        }
+    }
+    shares
+}
+
+impl HouseholdCompositionRecord {
+    /// The share of people in households living in each of the most detailed household types, which sum to 1 unless the area has no households
+    pub fn distribution(&self) -> EnumMap<HouseholdComposition, f64> {
+        let has_children = |cell: HouseholdComposition| self.values.iter().any(|(child, _)| child.parent() == Some(cell));
+        distribution(&self.values, self.values[HouseholdComposition::All], |cell| !has_children(cell))
+    }
+    /// Checks the people in households matches the "Lives in a household" count of KS101EW for the same area
+    pub fn check_against_population(&self, population: &PopulationRecord) -> Result<(), ParsingError> {
+        let residents = population.population_counts[AreaClassification::Total][PersonType::LivesInHousehold];
+        if population.geography_code != self.geography_code || residents != self.values[HouseholdComposition::All] {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Household composition")), Some(format!("{} has {} people in households, but {} has {}", self.geography_code, self.values[HouseholdComposition::All], population.geography_code, residents))));
+        }
+        Ok(())
+    }
+}
+
+impl HouseholdSizeRecord {
+    pub fn households(&self) -> u32 {
+        self.values[HouseholdSize::All]
+    }
+    /// The share of households of each size, which sum to 1 unless the area has no households
+    pub fn distribution(&self) -> EnumMap<HouseholdSize, f64> {
+        distribution(&self.values, self.households(), |cell| cell != HouseholdSize::All)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::census_table::CensusTable;
+    use crate::census_table::tests::{cell_rows, parse_rows};
+
+    use super::{HouseholdComposition, HouseholdCompositionRecord, HouseholdSize, HouseholdSizeRecord};
+
+    fn parse<T: CensusTable>(cells: &[(&str, u32)]) -> HashMap<String, T> {
+        parse_rows(&cell_rows("E00000001", cells))
+    }
+
+    const SIZES: [(&str, u32); 9] = [
+        ("All categories: Household size", 10),
+        ("1 person in household", 3),
+        ("2 people in household", 4),
+        ("3 people in household", 1),
+        ("4 people in household", 1),
+        ("5 people in household", 1),
+        ("6 people in household", 0),
+        ("7 people in household", 0),
+        ("8 or more people in household", 0),
+    ];
+
+    #[test]
+    fn parses_household_sizes_with_checked_total() {
+        let records: HashMap<String, HouseholdSizeRecord> = parse(&SIZES);
+        let record = &records["E00000001"];
+        assert_eq!(record.households(), 10);
+        assert_eq!(record.distribution()[HouseholdSize::Two], 0.4);
+        assert!((record.distribution().values().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let mut wrong_total = SIZES;
+        wrong_total[0].1 = 11;
+        assert!(parse::<HouseholdSizeRecord>(&wrong_total).is_empty());
+    }
+
+    #[test]
+    fn checks_household_composition_subtotals() {
+        let mut cells = vec![
+            ("All categories: Household composition", 12),
+            ("One person household", 2),
+            ("One person household: Aged 65 and over", 1),
+            ("One person household: Other", 1),
+            ("One family only", 10),
+            ("One family only: All aged 65 and over", 2),
+            ("One family only: Married or same-sex civil partnership couple", 8),
+            ("One family only: Married or same-sex civil partnership couple: No children", 2),
+            ("One family only: Married or same-sex civil partnership couple: Dependent children", 6),
+            ("One family only: Married or same-sex civil partnership couple: All children non-dependent", 0),
+        ];
+        let records: HashMap<String, HouseholdCompositionRecord> = parse(&cells);
+        let distribution = records["E00000001"].distribution();
+        assert_eq!(distribution[HouseholdComposition::MarriedCoupleDependentChildren], 0.5);
+        assert_eq!(distribution[HouseholdComposition::OneFamily], 0.0);
+        assert!((distribution.values().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        cells[1].1 = 3;
+        assert!(parse::<HouseholdCompositionRecord>(&cells).is_empty());
+    }
+}
@@ -5,23 +5,52 @@ use std::fmt::{Debug, Display, Formatter};
 use std::fs::File;
 use std::io::Write;
 use std::iter::Map;
-use std::time::Instant;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{stream, StreamExt, TryStreamExt};
 use log::{debug, error, info, warn};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
 use serde_json::{Number, Value};
+use tokio::sync::mpsc;
 
+use crate::census_bulk_files::bulk_archive_url;
+use crate::census_file_name_parse::GeographyLevel;
+use crate::census_table::{build_record, CensusTable};
+use crate::download_checkpoint::{write_atomically, DownloadCheckpoint};
+use crate::download_manifest::DownloadManifest;
+use crate::geography::{GeographyHierarchy, GeographySelector};
+use crate::nomis_query::NomisQuery;
 use crate::parsing_error::{ParsingError, ParsingErrorType};
-use crate::population_and_density_per_output_area::{PopulationRecord, PreProcessingRecord, SELECTED_COLUMNS};
+use crate::population_and_density_per_output_area::{PreProcessingRecord, SELECTED_COLUMNS};
+use crate::progress::{LogProgress, NoProgress, ProgressReporter, ProgressTracker};
+use crate::request_policy::{RateLimiter, RetryPolicy};
+use crate::response_cache::{CacheMode, ResponseCache};
+use crate::sdmx_data::parse_observations;
+use crate::table_schema::{Dimension, TableSchema};
 
-const ENGLAND_OUTPUT_AREAS_CODE: &str = "2092957699TYPE299";
 const NOMIS_API: &str = "https://www.nomisweb.co.uk/api/v01/";
+const DEFAULT_CONCURRENT_REQUESTS: usize = 8;
+const DEFAULT_REQUESTS_PER_SECOND: f64 = 4.0;
+const API_KEY_VARIABLE: &str = "NOMIS_API_KEY";
+/// The most cells NOMIS will return in a single anonymous request
+const ANONYMOUS_CELL_LIMIT: usize = 25_000;
+/// The most cells NOMIS will return in a single request with a `uid`
+const REGISTERED_CELL_LIMIT: usize = 1_000_000;
+/// How many parsed records can be waiting for the receiver of `stream_table`
+const STREAM_CHANNEL_SIZE: usize = 1024;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct TableInfo {
-    id: String,
-    coded_name: String,
-    source: String,
-    metadata: String,
-    keywords: Vec<String>,
-    geo_level: Vec<String>,
+    pub id: String,
+    pub coded_name: String,
+    pub source: String,
+    pub metadata: String,
+    pub keywords: Vec<String>,
+    pub geo_level: Vec<String>,
 }
 
 
@@ -58,22 +87,83 @@ impl Debug for TableInfo {
     }
 }
 
+#[derive(Clone)]
 pub struct DataFetcher {
     client: reqwest::Client,
+    /// The root of the NOMIS API, which all request paths are appended to
+    base_url: String,
+    /// The maximum number of pages to request from NOMIS at once
+    max_concurrent_requests: usize,
+    retry_policy: RetryPolicy,
+    /// Shared by every request, to stay within the NOMIS fair use terms
+    rate_limiter: Arc<RateLimiter>,
+    cache: Option<ResponseCache>,
+    /// The NOMIS `uid`, which raises the row limit for each request
+    api_key: Option<String>,
+    /// Told how many pages of each table have been downloaded
+    progress: Arc<dyn ProgressReporter>,
 }
 
 impl Default for DataFetcher {
     fn default() -> Self {
-        DataFetcher { client: reqwest::Client::default() }
+        DataFetcher {
+            client: reqwest::Client::default(),
+            base_url: String::from(NOMIS_API),
+            max_concurrent_requests: DEFAULT_CONCURRENT_REQUESTS,
+            retry_policy: RetryPolicy::default(),
+            rate_limiter: Arc::new(RateLimiter::new(DEFAULT_REQUESTS_PER_SECOND)),
+            cache: None,
+            api_key: None,
+            progress: Arc::new(LogProgress),
+        }
+    }
+}
+
+/// Groups consecutive rows for the same area, and builds a record once all of an area's rows have been seen
+struct AreaGrouper<T: CensusTable> {
+    current_area: String,
+    buffer: Vec<PreProcessingRecord>,
+    table: PhantomData<T>,
+}
+
+impl<T: CensusTable> Default for AreaGrouper<T> {
+    fn default() -> Self {
+        AreaGrouper { current_area: String::new(), buffer: Vec::new(), table: PhantomData }
+    }
+}
+
+impl<T: CensusTable> AreaGrouper<T> {
+    /// Adds a row, returning the previous area's record if this row starts a new area
+    fn push(&mut self, record: PreProcessingRecord) -> Option<T> {
+        let mut finished = None;
+        if record.geography_name != self.current_area {
+            finished = self.finish();
+            self.current_area = String::from(&record.geography_name);
+        }
+        self.buffer.push(record);
+        finished
+    }
+    /// Builds the record for the rows seen so far, logging it if they don't form a valid record
+    fn finish(&mut self) -> Option<T> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        match build_record(std::mem::take(&mut self.buffer)) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
     }
 }
 
-fn extract_value_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a Value, ParsingError> {
+pub(crate) fn extract_value_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a Value, ParsingError> {
     let object = object.get(name).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(name.to_string())))?;
     Ok(object)
 }
 
-fn extract_string_from_json(object: &Value, name: &str) -> Result<String, ParsingError> {
+pub(crate) fn extract_string_from_json(object: &Value, name: &str) -> Result<String, ParsingError> {
     let object = object.get(name).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(name.to_string())))?;
     if let Value::Number(n) = object {
         return Ok(n.to_string());
@@ -82,13 +172,13 @@ fn extract_string_from_json(object: &Value, name: &str) -> Result<String, Parsin
     Ok(object.to_string())
 }
 
-fn extract_array_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a Vec<Value>, ParsingError> {
+pub(crate) fn extract_array_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a Vec<Value>, ParsingError> {
     let object = object.get(name).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(name.to_string())))?;
     let object = object.as_array().ok_or_else(|| ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Array")), Some(name.to_string())))?;
     Ok(object)
 }
 
-fn extract_map_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a serde_json::Map<String, Value>, ParsingError> {
+pub(crate) fn extract_map_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a serde_json::Map<String, Value>, ParsingError> {
     let object = object.get(name).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(name.to_string())))?;
     let object = object.as_object().ok_or_else(|| ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Array: ")), Some(name.to_string())))?;
     Ok(object)
@@ -96,16 +186,85 @@ fn extract_map_from_json<'a>(object: &'a Value, name: &str) -> Result<&'a serde_
 
 
 impl DataFetcher {
+    /// Sends requests to a different NOMIS API root (such as a local stand in), which must end with a `/`
+    pub fn with_base_url(mut self, base_url: &str) -> DataFetcher {
+        self.base_url = base_url.to_string();
+        self
+    }
+    /// Sets the maximum number of page requests that can be in flight at once
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> DataFetcher {
+        self.max_concurrent_requests = max_concurrent_requests.max(1);
+        self
+    }
+    /// Sets how failed requests are retried
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> DataFetcher {
+        self.retry_policy = retry_policy;
+        self
+    }
+    /// Limits how many requests are started each second, where 0 disables the limit
+    pub fn with_rate_limit(mut self, requests_per_second: f64) -> DataFetcher {
+        self.rate_limiter = Arc::new(RateLimiter::new(requests_per_second));
+        self
+    }
+    /// Stores responses in the given directory, and depending on the mode, serves requests from it
+    pub fn with_cache(mut self, directory: &str, mode: CacheMode) -> Result<DataFetcher, ParsingError> {
+        self.cache = Some(ResponseCache::new(directory, mode)?);
+        Ok(self)
+    }
+    /// Sends the given NOMIS API key with every request
+    pub fn with_api_key(mut self, api_key: &str) -> DataFetcher {
+        self.api_key = Some(api_key.to_string()).filter(|api_key| !api_key.is_empty());
+        self
+    }
+    /// Reads the NOMIS API key from the `NOMIS_API_KEY` environment variable, if it is set
+    pub fn with_api_key_from_env(self) -> DataFetcher {
+        match std::env::var(API_KEY_VARIABLE) {
+            Ok(api_key) => {
+                info!("Using the NOMIS API key from {}", API_KEY_VARIABLE);
+                self.with_api_key(&api_key)
+            }
+            Err(_) => self,
+        }
+    }
+    /// Reports the progress of every table download to the given reporter, instead of the log
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressReporter>) -> DataFetcher {
+        self.progress = progress;
+        self
+    }
+    /// The most rows NOMIS will return in a single request, which is higher for registered users
+    pub fn cell_limit(&self) -> usize {
+        if self.api_key.is_some() { REGISTERED_CELL_LIMIT } else { ANONYMOUS_CELL_LIMIT }
+    }
+    /// The largest page size allowed, to use as few requests as possible
+    pub fn default_page_size(&self) -> usize {
+        self.cell_limit()
+    }
     pub async fn get_datasets(&self) -> Result<Value, ParsingError> {
-        let api: String = format!("{}dataset/def.sdmx.json?search=c2011*", NOMIS_API);
-        println!("Making request to: {}", api);
-        let request = self.client.get(api).send().await?;
-        println!("Got response: {:?}", request);
-        let data = request.text().await?;
+        let query = NomisQuery::new("dataset/def.sdmx.json").with_search("c2011*");
+        let data = self.get_query(&query).await?;
         let json: Value = serde_json::from_str(&data)?;
         Ok(json)
     }
 
+    /// Downloads the bulk release of a table at a geography level (such as `KS101EW` and `oa`) into the given directory, ready to be indexed by `BulkFileIndex`
+    ///
+    /// Returns the path of the downloaded archive, skipping the download if it already exists
+    pub async fn download_bulk_archive(&self, table: &str, geography: GeographyLevel, directory: &str) -> Result<std::path::PathBuf, ParsingError> {
+        let url = bulk_archive_url(table, geography);
+        let path = std::path::Path::new(directory).join(url.rsplit('/').next().unwrap_or_default());
+        if path.exists() {
+            info!("Bulk archive {:?} has already been downloaded", path);
+            return Ok(path);
+        }
+        std::fs::create_dir_all(directory)?;
+        self.rate_limiter.wait().await;
+        info!("Downloading bulk archive {}", url);
+        let data = self.client.get(&url).send().await?.error_for_status()?.bytes().await?;
+        write_atomically(&path, &data)?;
+        Ok(path)
+    }
+
+    /// Parses every table in the dataset definitions, use a `TableCatalogue` to search them
     pub fn parse_jsontable_list(json: Value) -> Result<Vec<TableInfo>, ParsingError> {
         let mut tables = Vec::new();
 
@@ -126,87 +285,479 @@ impl DataFetcher {
                 annotation_properties.insert(title, text);
             }
             let table_info = TableInfo::try_from(annotation_properties);
-            if let Ok(table_info) = table_info {
-                if table_info.geo_level.contains(&"oa".to_string()) {
-                    println!("{}", table_info);
+            match table_info {
+                Ok(table_info) => {
+                    debug!("{}", table_info);
                     tables.push(table_info);
                 }
+                Err(e) => warn!("Skipping table {}: {}", key.get("id").unwrap_or(&Value::Null), e)
             }
         }
         Ok(tables)
         //println!("JSON Data: {:?}", json);
         //println!("{:?}", data);
     }
-    pub async fn get_geography_code(&self, id: String) -> Result<(), ParsingError> {
-        let mut path = String::from("https://www.nomisweb.co.uk/api/v01/dataset/");
-        path.push_str(&id);
-        path.push_str("/geography.def.sdmx.json");
-        println!("Making request to: {}", path);
-        let request = self.client.get(path).send().await?;
-        println!("Got response: {:?}", request);
-        let data = request.text().await?;
-        println!("{}", data);
-        /*        for line in data.split("\n") {
-                    println!("{}", line);
-                }
-        */        Ok(())
-    }
-    pub async fn get_table(&self, id: String, number_of_records: usize, page_size: usize) -> Result<String, ParsingError> {
-        let mut path = String::from(NOMIS_API);
-        path.push_str(&id);
-        path.push_str(".data.csv");
-        path.push_str("?geography=");
-        path.push_str(ENGLAND_OUTPUT_AREAS_CODE);
-        path.push_str("&recordlimit=");
-        path.push_str(page_size.to_string().as_str());
+    /// Fetches the top level geographies the given table is available for, and the geography types within them
+    pub async fn get_geography_code(&self, id: String) -> Result<GeographyHierarchy, ParsingError> {
+        let query = NomisQuery::new(&format!("dataset/{}/geography.def.sdmx.json", id));
+        let data = self.get_query(&query).await?;
+        let json: Value = serde_json::from_str(&data)?;
+        GeographyHierarchy::from_json(&json)
+    }
+    /// Fetches the geographies within the given parent code, such as every region in England, or every output area in `2092957699TYPE299`
+    pub async fn get_child_geographies(&self, id: String, parent_code: &str) -> Result<GeographyHierarchy, ParsingError> {
+        let query = NomisQuery::new(&format!("dataset/{}/geography/{}.def.sdmx.json", id, parent_code));
+        let data = self.get_query(&query).await?;
+        let json: Value = serde_json::from_str(&data)?;
+        GeographyHierarchy::from_json(&json)
+    }
+    /// Fetches the codes of a single dimension of a table, such as `cell` or `rural_urban`
+    pub async fn get_dimension(&self, id: String, dimension: &str) -> Result<Dimension, ParsingError> {
+        let query = NomisQuery::new(&format!("dataset/{}/{}.def.sdmx.json", id, dimension));
+        let data = self.get_query(&query).await?;
+        let json: Value = serde_json::from_str(&data)?;
+        Dimension::from_json(dimension, &json)
+    }
+    /// Fetches the given dimensions of a table, such as `table_schema::POPULATION_DIMENSIONS`
+    pub async fn get_table_schema(&self, id: String, dimensions: &[&str]) -> Result<TableSchema, ParsingError> {
+        let mut schema = TableSchema { table_id: id.clone(), dimensions: Vec::with_capacity(dimensions.len()) };
+        for dimension in dimensions {
+            schema.dimensions.push(self.get_dimension(id.clone(), dimension).await?);
+        }
+        Ok(schema)
+    }
+    /// Downloads every page of the given table for the selected geographies, with up to `max_concurrent_requests` requests in flight
+    ///
+    /// Selections too long for a single request are split over several, with the column headings only kept from the first
+    /// If `number_of_records` isn't given, it is read from the `RECORD_COUNT` column of the first page of each request
+    /// Pages are reassembled in `RecordOffset` order, so the output matches a sequential download
+    pub async fn get_table(&self, id: String, geography: &GeographySelector, number_of_records: Option<usize>, page_size: usize) -> Result<String, ParsingError> {
+        self.get_table_query(&NomisQuery::table(&id).with_select(SELECTED_COLUMNS), geography, number_of_records, page_size).await
+    }
+    /// Downloads every page of a table query, such as one filtered to certain cells or measures, in the same way as `get_table`
+    ///
+    /// Any geography, paging or column heading parameters on the query are replaced
+    pub async fn get_table_query(&self, query: &NomisQuery, geography: &GeographySelector, number_of_records: Option<usize>, page_size: usize) -> Result<String, ParsingError> {
+        let parameters = geography.to_parameters();
+        let number_of_records = DataFetcher::record_count_override(number_of_records, parameters.len());
         let mut data = String::new();
-	let start_time=Instant::now();
-        for index in 0..(number_of_records as f64 / page_size as f64).ceil() as usize {
-            let mut to_send = path.clone();
-            to_send.push_str("&RecordOffset=");
-            to_send.push_str((index * page_size).to_string().as_str());
-            if index != 0 {
-                to_send.push_str("&ExcludeColumnHeadings=true");
+        for (part, geography) in parameters.iter().enumerate() {
+            let part_data = self.get_table_part(query, geography, number_of_records, page_size).await?;
+            data.push_str(if part == 0 { &part_data } else { DataFetcher::strip_headings(&part_data) });
+        }
+        Ok(data)
+    }
+    async fn get_table_part(&self, query: &NomisQuery, geography: &str, number_of_records: Option<usize>, page_size: usize) -> Result<String, ParsingError> {
+        let page_size = self.page_size_within_limit(page_size);
+        let progress = ProgressTracker::new(self.progress.as_ref(), &format!("Downloading pages of {}", query.table_id().unwrap_or_default()), None);
+        let first_page = self.get_table_page(query, geography, page_size, 0, DataFetcher::first_page_limit(number_of_records, page_size), None).await?;
+        let (table_records, number_of_records) = DataFetcher::record_counts(&first_page, number_of_records)?;
+        let page_count = DataFetcher::page_count(number_of_records, page_size);
+        progress.set_total(page_count);
+        progress.add(1);
+        let pages: Vec<String> = stream::iter(1..page_count)
+            .map(|index| self.get_table_page(query, geography, page_size, index, DataFetcher::page_limit(number_of_records, page_size, index), Some(table_records)))
+            .buffered(self.max_concurrent_requests)
+            .inspect_ok(|_| progress.add(1))
+            .try_collect()
+            .await?;
+        progress.finish();
+        Ok(first_page + &pages.concat())
+    }
+    /// Downloads the given table into `directory`, saving each page as soon as it arrives
+    ///
+    /// If a previous download into the same directory was interrupted, only the pages missing from its checkpoint are requested
+    /// Selections too long for a single request are split over several, each checkpointed in its own `part_` subdirectory
+    /// If `number_of_records` isn't given, it is read from the `RECORD_COUNT` column of the first page of each request
+    pub async fn download_table(&self, id: String, geography: &GeographySelector, number_of_records: Option<usize>, page_size: usize, directory: &str) -> Result<String, ParsingError> {
+        self.download_table_query(&NomisQuery::table(&id).with_select(SELECTED_COLUMNS), geography, number_of_records, page_size, directory).await
+    }
+    /// Downloads a table query into `directory`, in the same way as `download_table`
+    ///
+    /// Any geography, paging or column heading parameters on the query are replaced
+    pub async fn download_table_query(&self, query: &NomisQuery, geography: &GeographySelector, number_of_records: Option<usize>, page_size: usize, directory: &str) -> Result<String, ParsingError> {
+        Ok(self.download_table_pages(query, geography, number_of_records, page_size, directory).await?.0)
+    }
+    /// Downloads a table into `directory` in the same way as `download_table`, then writes it to `output` with a `DownloadManifest` next to it
+    ///
+    /// The record count is always read from the table, and the manifest's metadata text is taken from `table`, if it is given
+    pub async fn save_table(&self, id: String, geography: &GeographySelector, page_size: usize, directory: &str, output: &str, table: Option<&TableInfo>) -> Result<(String, DownloadManifest), ParsingError> {
+        let query = NomisQuery::table(&id).with_select(SELECTED_COLUMNS);
+        let (data, pages) = self.download_table_pages(&query, geography, None, page_size, directory).await?;
+        let queries = self.table_queries(&id, geography)?;
+        let manifest = DownloadManifest::new(&id, queries, &geography.to_string(), page_size, pages, &data, table.map(|table| table.metadata.clone()))?;
+        manifest.write(std::path::Path::new(output), &data)?;
+        info!("Saved {} with {} rows to {}", id, manifest.row_count, output);
+        Ok((data, manifest))
+    }
+    /// The request `save_table` records in the manifest for each part of the geography selection
+    pub fn table_queries(&self, id: &str, geography: &GeographySelector) -> Result<Vec<String>, ParsingError> {
+        let query = NomisQuery::table(id).with_select(SELECTED_COLUMNS);
+        geography.to_parameters().iter()
+            .map(|parameter| Ok(query.clone().with_geography(parameter).to_url(&self.base_url)?.to_string()))
+            .collect()
+    }
+    /// Downloads every part of the geography selection, returning the joined data and the total number of pages
+    async fn download_table_pages(&self, query: &NomisQuery, geography: &GeographySelector, number_of_records: Option<usize>, page_size: usize, directory: &str) -> Result<(String, usize), ParsingError> {
+        let parameters = geography.to_parameters();
+        let number_of_records = DataFetcher::record_count_override(number_of_records, parameters.len());
+        if parameters.len() == 1 {
+            return self.download_table_part(query, &parameters[0], number_of_records, page_size, directory).await;
+        }
+        let mut data = String::new();
+        let mut pages = 0;
+        for (part, geography) in parameters.iter().enumerate() {
+            let part_directory = format!("{}/part_{}", directory, part);
+            let (part_data, part_pages) = self.download_table_part(query, geography, number_of_records, page_size, &part_directory).await?;
+            data.push_str(if part == 0 { &part_data } else { DataFetcher::strip_headings(&part_data) });
+            pages += part_pages;
+        }
+        Ok((data, pages))
+    }
+    async fn download_table_part(&self, query: &NomisQuery, geography: &str, number_of_records: Option<usize>, page_size: usize, directory: &str) -> Result<(String, usize), ParsingError> {
+        let page_size = self.page_size_within_limit(page_size);
+        let first_page = self.get_table_page(query, geography, page_size, 0, DataFetcher::first_page_limit(number_of_records, page_size), None).await?;
+        let (table_records, number_of_records) = DataFetcher::record_counts(&first_page, number_of_records)?;
+        let page_count = DataFetcher::page_count(number_of_records, page_size);
+        let table_id = query.table_id().unwrap_or_default();
+        let table_query = query.clone().with_geography(geography).to_url(&self.base_url)?.to_string();
+        let mut checkpoint = DownloadCheckpoint::load_or_create(directory, table_id, geography, &table_query, page_size, number_of_records, page_count)?;
+        if !checkpoint.completed_pages.contains(&0) {
+            checkpoint.save_page(0, &first_page)?;
+        }
+        // Pages saved by an earlier download count as finished, but not towards the throughput
+        let progress = ProgressTracker::new(self.progress.as_ref(), &format!("Downloading pages of {}", table_id), Some(page_count));
+        progress.add(checkpoint.completed_pages.len());
+        let mut pages = stream::iter(checkpoint.remaining_pages())
+            .map(|index| {
+                async move { (index, self.get_table_page(query, geography, page_size, index, DataFetcher::page_limit(number_of_records, page_size, index), Some(table_records)).await) }
+            })
+            .buffer_unordered(self.max_concurrent_requests);
+        while let Some((index, page)) = pages.next().await {
+            checkpoint.save_page(index, &page?)?;
+            progress.add(1);
+        }
+        progress.finish();
+        Ok((checkpoint.assemble()?, page_count))
+    }
+    /// Lowers the page size to the cell limit, as NOMIS never returns more rows than that, so larger pages would be rejected as truncated
+    fn page_size_within_limit(&self, page_size: usize) -> usize {
+        if page_size > self.cell_limit() {
+            warn!("Using pages of {} rows instead of {}, the cell limit for {} requests", self.cell_limit(), page_size, if self.api_key.is_some() { "registered" } else { "anonymous" });
+        }
+        page_size.min(self.cell_limit()).max(1)
+    }
+    /// A record count only makes sense for a single request, so it is ignored if the geography selection has been split
+    fn record_count_override(number_of_records: Option<usize>, parts: usize) -> Option<usize> {
+        if parts > 1 && number_of_records.is_some() {
+            warn!("Ignoring the record count, as the geography selection is split over {} requests", parts);
+            return None;
+        }
+        number_of_records
+    }
+    /// Removes the column headings from the start of a CSV download
+    fn strip_headings(data: &str) -> &str {
+        data.split_once('\n').map(|(_, rows)| rows).unwrap_or("")
+    }
+    /// Reads `RECORD_COUNT` from the first row of the first page, returning it with the number of records to download, which the given number of records can lower
+    ///
+    /// The given number of records is only used as the table's record count if the page has no `RECORD_COUNT` column
+    fn record_counts(first_page: &str, number_of_records: Option<usize>) -> Result<(usize, usize), ParsingError> {
+        let table_records = match (DataFetcher::read_record_count(first_page), number_of_records) {
+            (Ok(record_count), _) => record_count,
+            (Err(_), Some(number_of_records)) => number_of_records,
+            (Err(e), None) => return Err(e),
+        };
+        info!("Table has {} records", table_records);
+        Ok((table_records, number_of_records.map_or(table_records, |number_of_records| number_of_records.min(table_records))))
+    }
+    /// The rows to request for the first page, before the table's record count is known
+    fn first_page_limit(number_of_records: Option<usize>, page_size: usize) -> usize {
+        number_of_records.map_or(page_size, |number_of_records| number_of_records.min(page_size))
+    }
+    /// The rows to request for a page, so the download stops after `number_of_records`
+    fn page_limit(number_of_records: usize, page_size: usize, index: usize) -> usize {
+        number_of_records.saturating_sub(index * page_size).min(page_size)
+    }
+    /// Calculates how many pages are needed to download the whole table
+    fn page_count(number_of_records: usize, page_size: usize) -> usize {
+        ((number_of_records as f64 / page_size as f64).ceil() as usize).max(1)
+    }
+    /// Reads the `RECORD_COUNT` column from the first row of a CSV page, or 0 if the page has no rows
+    fn read_record_count(page: &str) -> Result<usize, ParsingError> {
+        let mut reader = csv::Reader::from_reader(page.as_bytes());
+        let column = reader.headers()?.iter().position(|header| header == "RECORD_COUNT").ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(String::from("RECORD_COUNT"))))?;
+        match reader.records().next() {
+            Some(record) => {
+                let record = record?;
+                let count = record.get(column).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(String::from("RECORD_COUNT"))))?;
+                Ok(count.parse()?)
+            }
+            None => Ok(0)
+        }
+    }
+    /// Requests `limit` rows of a table, starting at page `index`, retrying if it comes back with fewer rows than expected
+    ///
+    /// The expected rows are checked against `table_records`, the table's `RECORD_COUNT`, which is read from the page itself for the first page
+    async fn get_table_page(&self, query: &NomisQuery, geography: &str, page_size: usize, index: usize, limit: usize, table_records: Option<usize>) -> Result<String, ParsingError> {
+        let query = query.clone()
+            .with_geography(geography)
+            .with_record_limit(limit)
+            .with_record_offset(index * page_size)
+            .with_exclude_column_headings(index != 0);
+        let url = query.to_url(&self.base_url)?.to_string();
+        let expected_rows = |page: &str| {
+            let table_records = match table_records {
+                Some(table_records) => table_records,
+                None => DataFetcher::read_record_count(page).unwrap_or(limit),
+            };
+            table_records.saturating_sub(index * page_size).min(limit)
+        };
+        let page = self.get_with_retries(url, |page| self.check_page_complete(page, index == 0, expected_rows(page))).await?;
+        let cells = expected_rows(&page);
+        info!("Page {} used {} of the {} cell limit ({:.1}%)", index, cells, self.cell_limit(), 100.0 * cells as f64 / self.cell_limit() as f64);
+        Ok(page)
+    }
+    /// Checks that a CSV page wasn't cut short by a dropped connection
+    ///
+    /// A page cut short at the anonymous cell limit, even though an API key was sent, fails with `ApiKeyRejected`, as retrying won't help
+    fn check_page_complete(&self, page: &str, has_headers: bool, expected_rows: usize) -> Result<(), ParsingError> {
+        if expected_rows > 0 && !page.ends_with('\n') {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Truncated CSV page")), Some(String::from("Page does not end with a new line"))));
+        }
+        let rows = csv::ReaderBuilder::new().has_headers(has_headers).from_reader(page.as_bytes()).records().count();
+        if self.api_key.is_some() && rows == ANONYMOUS_CELL_LIMIT && expected_rows > rows {
+            return Err(ParsingError::new(ParsingErrorType::ApiKeyRejected, Some(format!("Expected {} rows, but NOMIS only returned the anonymous limit of {}, so check the key in {}", expected_rows, rows, API_KEY_VARIABLE))));
+        }
+        if rows != expected_rows {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Truncated CSV page")), Some(format!("Expected {} rows, got {}", expected_rows, rows))));
+        }
+        Ok(())
+    }
+    /// Makes a single request, without any paging
+    pub async fn get_query(&self, query: &NomisQuery) -> Result<String, ParsingError> {
+        self.get_with_retries(query.to_url(&self.base_url)?.to_string(), |_| Ok(())).await
+    }
+    /// Makes a rate limited request, retrying with backoff on network errors, HTTP 429/5xx responses, or if `validate` rejects the response with anything but `ApiKeyRejected`
+    ///
+    /// If a cache is set, cached responses are used according to its mode, and every successful response is stored
+    /// The API key is added to the request here, so `url` must not contain it
+    async fn get_with_retries<F: Fn(&str) -> Result<(), ParsingError>>(&self, url: String, validate: F) -> Result<String, ParsingError> {
+        if let Some(cache) = &self.cache {
+            match cache.mode() {
+                CacheMode::OfflineOnly => {
+                    let data = cache.get_required(&url)?;
+                    validate(&data)?;
+                    return Ok(data);
+                }
+                CacheMode::CacheFirst => {
+                    if let Some(data) = cache.get(&url)? {
+                        match validate(&data) {
+                            Ok(()) => return Ok(data),
+                            Err(e) => warn!("Ignoring invalid cached response for {}: {}", url, e),
+                        }
+                    }
+                }
+                CacheMode::Online => {}
+            }
+        }
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter.wait().await;
+            info!("Making request to: {}", url);
+            let mut retry_after = None;
+            let mut request = self.client.get(&url);
+            if let Some(api_key) = &self.api_key {
+                // Added here rather than to the query, so the key never appears in logs, errors or cache keys
+                request = request.query(&[("uid", api_key)]);
             }
-            to_send.push_str("&select=");
-            to_send.push_str(SELECTED_COLUMNS);
-            info!("Making request to: {}", to_send);
-            let request = self.client.get(to_send).send().await?;
-            debug!("Got response: {:?}", request);
-            let new_data = request.text().await?;
-            data.push_str(new_data.as_str());
-	    info!("Completed request {} in {:?}",index,start_time.elapsed());
-        }
-        return Ok(data);
-    }
-    pub fn parse_table<R: std::io::Read>(mut data: csv::Reader<R>) -> Result<HashMap<String, PopulationRecord>, ParsingError> {
-        //let mut csv_reader = csv::Reader::from_reader(data.as_bytes());
+            let error = match request.send().await {
+                Ok(response) => {
+                    debug!("Got response: {} with headers {:?}", response.status(), response.headers());
+                    let status = response.status();
+                    if status.is_success() {
+                        match response.text().await {
+                            Ok(data) => match validate(&data) {
+                                Ok(()) => {
+                                    if let Some(cache) = &self.cache {
+                                        cache.put(&url, &data)?;
+                                    }
+                                    return Ok(data);
+                                }
+                                Err(e) if matches!(e.error_type(), ParsingErrorType::ApiKeyRejected) => {
+                                    error!("Request to {} failed: {}", url, e);
+                                    return Err(e);
+                                }
+                                Err(e) => e,
+                            },
+                            Err(e) => ParsingError::from(e),
+                        }
+                    } else if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+                        retry_after = response.headers().get(RETRY_AFTER).and_then(|value| value.to_str().ok()).and_then(|value| value.parse().ok()).map(Duration::from_secs);
+                        ParsingError::new(ParsingErrorType::NetworkError, Some(format!("HTTP {} from {}", status, url)))
+                    } else {
+                        return Err(ParsingError::new(ParsingErrorType::NetworkError, Some(format!("HTTP {} from {}", status, url))));
+                    }
+                }
+                Err(e) => ParsingError::from(e),
+            };
+            if attempt >= self.retry_policy.max_retries {
+                error!("Request to {} failed after {} attempts: {}", url, attempt + 1, error);
+                return Err(error);
+            }
+            let delay = self.retry_policy.delay_for_attempt(attempt).max(retry_after.unwrap_or_default());
+            warn!("Request to {} failed ({}), retrying in {:?}", url, error, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+    /// Parses a long format CSV download into one record per area, such as a `PopulationRecord` for KS101EW
+    pub fn parse_table<T: CensusTable, R: std::io::Read>(mut data: csv::Reader<R>, progress: &dyn ProgressReporter) -> Result<HashMap<String, T>, ParsingError> {
+        let tracker = ProgressTracker::new(progress, "Parsing rows", None);
+        let records = data.deserialize().inspect(|_| tracker.add(1)).filter_map(|line: Result<PreProcessingRecord, csv::Error>| {
+            line.map_err(|e| error!("{}", e)).ok()
+        });
+        let output = DataFetcher::parse_records(records);
+        tracker.finish();
+        Ok(output)
+    }
+    /// Groups long format records by area, skipping (and logging) any area that doesn't form a valid record
+    pub fn parse_records<T: CensusTable, I: IntoIterator<Item=PreProcessingRecord>>(records: I) -> HashMap<String, T> {
         let mut output = HashMap::new();
-
-        let mut current_area = String::from("");
-        let mut buffer = Vec::new();
-
-        for line in data.deserialize() {
-            let record: Result<PreProcessingRecord, csv::Error> = line;
+        let mut grouper = AreaGrouper::<T>::default();
+        for record in records {
+            if let Some(table_record) = grouper.push(record) {
+                output.insert(table_record.geography_code().to_string(), table_record);
+            }
+        }
+        if let Some(table_record) = grouper.finish() {
+            output.insert(table_record.geography_code().to_string(), table_record);
+        }
+        output
+    }
+    /// Downloads a table and parses it into one record per area
+    pub async fn get_census_table<T: CensusTable>(&self, geography: &GeographySelector, page_size: usize) -> Result<HashMap<String, T>, ParsingError> {
+        let data = self.get_table(T::TABLE_ID.to_string(), geography, None, page_size).await?;
+        DataFetcher::parse_table(csv::Reader::from_reader(data.as_bytes()), self.progress.as_ref())
+    }
+    /// Downloads a table in the `.data.sdmx.json` format, converted to the same long format records as the CSV download
+    ///
+    /// The SDMX-JSON pages don't give a record count, so it is read from a single row CSV request for each part of the geography selection
+    /// Pages are then requested one at a time until every record has arrived, failing if a page comes back empty first
+    pub async fn get_table_sdmx(&self, id: String, geography: &GeographySelector, page_size: usize) -> Result<Vec<PreProcessingRecord>, ParsingError> {
+        let page_size = self.page_size_within_limit(page_size);
+        let query = NomisQuery::table(&id).with_path(&format!("{}.data.sdmx.json", id));
+        let count_query = NomisQuery::table(&id).with_select("RECORD_COUNT").with_record_limit(1);
+        let mut observations = Vec::new();
+        for geography in geography.to_parameters() {
+            let record_count = DataFetcher::read_record_count(&self.get_query(&count_query.clone().with_geography(&geography)).await?)?;
+            let mut part_observations = 0;
+            for index in 0.. {
+                if part_observations >= record_count {
+                    break;
+                }
+                let page_query = query.clone()
+                    .with_geography(&geography)
+                    .with_record_limit(page_size)
+                    .with_record_offset(part_observations);
+                let data = self.get_with_retries(page_query.to_url(&self.base_url)?.to_string(), |data| {
+                    serde_json::from_str::<Value>(data)?;
+                    Ok(())
+                }).await?;
+                let page = parse_observations(&serde_json::from_str(&data)?)?;
+                info!("Completed SDMX request {} with {} observations", index, page.len());
+                if page.is_empty() {
+                    break;
+                }
+                part_observations += page.len();
+                observations.extend(page);
+            }
+            if part_observations != record_count {
+                return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Incomplete SDMX-JSON download")), Some(format!("Expected {} observations of {}, got {}", record_count, id, part_observations))));
+            }
+        }
+        let record_count = observations.len() as u32;
+        Ok(observations.iter().enumerate().map(|(offset, observation)| observation.to_record(offset as u32, record_count)).collect())
+    }
+    /// Downloads a table as both CSV and SDMX-JSON, and checks they produce identical records
+    pub async fn check_formats_match<T: CensusTable + PartialEq>(&self, id: String, geography: &GeographySelector, page_size: usize) -> Result<HashMap<String, T>, ParsingError> {
+        let csv_data = self.get_table(id.clone(), geography, None, page_size).await?;
+        let from_csv = DataFetcher::parse_table(csv::Reader::from_reader(csv_data.as_bytes()), &NoProgress)?;
+        let from_sdmx: HashMap<String, T> = DataFetcher::parse_records(self.get_table_sdmx(id.clone(), geography, page_size).await?);
+        if from_csv.len() != from_sdmx.len() {
+            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Format mismatch")), Some(format!("{} has {} areas as CSV, but {} as SDMX-JSON", id, from_csv.len(), from_sdmx.len()))));
+        }
+        for (area, record) in from_csv.iter() {
+            if from_sdmx.get(area) != Some(record) {
+                return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Format mismatch")), Some(format!("Area {} of {} differs between CSV and SDMX-JSON", area, id))));
+            }
+        }
+        Ok(from_csv)
+    }
+    /// Downloads and parses a table page by page, sending each record through a bounded channel as soon as its area is complete
+    ///
+    /// Only a few pages are held in memory at once, however large the table is
+    /// If the download fails, the error is sent as the last item
+    pub fn stream_table<T: CensusTable + Send + 'static>(&self, id: String, geography: GeographySelector, number_of_records: Option<usize>, page_size: usize) -> mpsc::Receiver<Result<T, ParsingError>> {
+        let (sender, receiver) = mpsc::channel(STREAM_CHANNEL_SIZE);
+        let fetcher = self.clone();
+        tokio::spawn(async move {
+            let query = NomisQuery::table(&id).with_select(SELECTED_COLUMNS);
+            if let Err(e) = fetcher.stream_table_query(&query, &geography, number_of_records, page_size, &sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        receiver
+    }
+    async fn stream_table_query<T: CensusTable>(&self, query: &NomisQuery, geography: &GeographySelector, number_of_records: Option<usize>, page_size: usize, sender: &mpsc::Sender<Result<T, ParsingError>>) -> Result<(), ParsingError> {
+        let parameters = geography.to_parameters();
+        let number_of_records = DataFetcher::record_count_override(number_of_records, parameters.len());
+        let mut grouper = AreaGrouper::<T>::default();
+        let page_size = self.page_size_within_limit(page_size);
+        let progress = ProgressTracker::new(self.progress.as_ref(), &format!("Streaming pages of {}", query.table_id().unwrap_or_default()), None);
+        let mut total_pages = 0;
+        for geography in parameters.iter() {
+            let first_page = self.get_table_page(query, geography, page_size, 0, DataFetcher::first_page_limit(number_of_records, page_size), None).await?;
+            let (table_records, number_of_records) = DataFetcher::record_counts(&first_page, number_of_records)?;
+            let page_count = DataFetcher::page_count(number_of_records, page_size);
+            total_pages += page_count;
+            progress.set_total(total_pages);
+            progress.add(1);
+            let mut reader = csv::Reader::from_reader(first_page.as_bytes());
+            let headers = reader.headers()?.clone();
+            DataFetcher::stream_page(&mut grouper, reader, &headers, sender).await?;
+            let mut pages = stream::iter(1..page_count)
+                .map(|index| self.get_table_page(query, geography, page_size, index, DataFetcher::page_limit(number_of_records, page_size, index), Some(table_records)))
+                .buffered(self.max_concurrent_requests);
+            while let Some(page) = pages.next().await {
+                let page = page?;
+                let reader = csv::ReaderBuilder::new().has_headers(false).from_reader(page.as_bytes());
+                DataFetcher::stream_page(&mut grouper, reader, &headers, sender).await?;
+                progress.add(1);
+            }
+        }
+        progress.finish();
+        if let Some(pop_record) = grouper.finish() {
+            DataFetcher::send_record(sender, pop_record).await?;
+        }
+        Ok(())
+    }
+    async fn stream_page<T: CensusTable, R: std::io::Read>(grouper: &mut AreaGrouper<T>, mut reader: csv::Reader<R>, headers: &csv::StringRecord, sender: &mpsc::Sender<Result<T, ParsingError>>) -> Result<(), ParsingError> {
+        for row in reader.records() {
+            let record: Result<PreProcessingRecord, csv::Error> = row.and_then(|row| row.deserialize(Some(headers)));
             match record {
                 Ok(record) => {
-                    if record.geography_name != current_area {
-                        if !current_area.is_empty() {
-                            let pop_record = PopulationRecord::try_from(buffer);
-                            match pop_record {
-                                Ok(pop_record) => { output.insert(current_area, pop_record); },
-                                Err(e) => { error!("{}",e); }
-                            }
-                            buffer = Vec::new();
-                        }
-                        current_area = String::from(&record.geography_name);
+                    if let Some(pop_record) = grouper.push(record) {
+                        DataFetcher::send_record(sender, pop_record).await?;
                     }
-                    buffer.push(record);
                 }
-                Err(e) => error!("{}",e)
+                Err(e) => error!("{}", e)
             }
         }
-        Ok(output)
+        Ok(())
+    }
+    async fn send_record<T>(sender: &mpsc::Sender<Result<T, ParsingError>>, record: T) -> Result<(), ParsingError> {
+        sender.send(Ok(record)).await.map_err(|_| ParsingError::new(ParsingErrorType::IOError, Some(String::from("Record receiver was dropped"))))
     }
     pub async fn read_json(filename: String) -> Result<Value, String> {
         let mut file = File::open(filename).map_err(|e| format!("{:?}", e))?;
@@ -220,3 +771,364 @@ impl DataFetcher {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::download_manifest;
+    use crate::geography::{GeographySelector, GeographyType};
+    use crate::nomis_stand_in::NomisStandIn;
+    use crate::request_policy::RetryPolicy;
+    use crate::response_cache::CacheMode;
+
+    use crate::population_and_density_per_output_area::{AreaClassification, PersonType, PopulationRecord, PreProcessingRecord};
+    use crate::progress::NoProgress;
+    use crate::table_schema::POPULATION_DIMENSIONS;
+
+    use super::DataFetcher;
+
+    fn test_fetcher(stand_in: &NomisStandIn) -> DataFetcher {
+        DataFetcher::default()
+            .with_base_url(&stand_in.base_url())
+            .with_progress(Arc::new(NoProgress))
+            .with_rate_limit(0.0)
+            .with_retry_policy(RetryPolicy { max_retries: 2, initial_delay: Duration::from_millis(1), max_delay: Duration::from_millis(5) })
+    }
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("nomis_download_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[tokio::test]
+    async fn paged_download_matches_sequential_download() {
+        let stand_in = NomisStandIn::start().await;
+        let data = test_fetcher(&stand_in).with_max_concurrent_requests(4).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 64).await.unwrap();
+        assert_eq!(data, stand_in.full_table("NM_144_1"));
+        assert_eq!(stand_in.requests().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn bounds_concurrent_page_requests() {
+        let stand_in = NomisStandIn::start().await;
+        stand_in.delay_responses(Duration::from_millis(20));
+        let data = test_fetcher(&stand_in).with_max_concurrent_requests(3).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 50).await.unwrap();
+        assert_eq!(data, stand_in.full_table("NM_144_1"));
+        assert_eq!(stand_in.requests().len(), 10);
+        assert_eq!(stand_in.max_in_flight(), 3);
+    }
+
+    #[tokio::test]
+    async fn only_first_page_has_column_headings() {
+        let stand_in = NomisStandIn::start().await;
+        let data = test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100).await.unwrap();
+        assert_eq!(data.lines().filter(|line| line.starts_with("\"GEOGRAPHY_NAME\"")).count(), 1);
+        let requests = stand_in.requests();
+        assert!(!requests[0].contains("ExcludeColumnHeadings"));
+        assert!(requests[1..].iter().all(|request| request.contains("ExcludeColumnHeadings=true")));
+    }
+
+    #[tokio::test]
+    async fn splits_long_geography_selections() {
+        let stand_in = NomisStandIn::start().await;
+        let codes: Vec<u64> = (0..1000).map(|code| 1237320000 + code * 2).collect();
+        let geography = GeographySelector::from_numeric_codes(&codes);
+        let parts = geography.to_parameters().len();
+        assert_eq!(parts, 4);
+        let data = test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &geography, None, 250).await.unwrap();
+        let requests = stand_in.requests();
+        assert_eq!(requests.len(), 2 * parts);
+        assert!(requests.iter().all(|request| request.len() < 4500));
+        assert_eq!(data.lines().filter(|line| line.starts_with("\"GEOGRAPHY_NAME\"")).count(), 1);
+        assert_eq!(data.lines().count(), 1 + parts * 500);
+    }
+
+    #[tokio::test]
+    async fn streamed_records_match_parsed_table() {
+        let stand_in = NomisStandIn::start().await;
+        let parsed: HashMap<String, PopulationRecord> = DataFetcher::parse_table(csv::Reader::from_reader(stand_in.full_table("NM_144_1").as_bytes()), &NoProgress).unwrap();
+        let fetched = test_fetcher(&stand_in).get_census_table::<PopulationRecord>(&GeographySelector::england_output_areas(), 100).await.unwrap();
+        assert_eq!(fetched, parsed);
+        let mut receiver = test_fetcher(&stand_in).stream_table::<PopulationRecord>("NM_144_1".to_string(), GeographySelector::england_output_areas(), None, 64);
+        let mut streamed = Vec::new();
+        while let Some(record) = receiver.recv().await {
+            streamed.push(record.unwrap());
+        }
+        assert!(!streamed.is_empty());
+        assert_eq!(streamed.len(), parsed.len());
+        for record in streamed {
+            assert_eq!(record, parsed[&record.geography_code]);
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_api_key_with_every_request() {
+        let stand_in = NomisStandIn::start().await;
+        let fetcher = test_fetcher(&stand_in).with_api_key("secret-key");
+        assert_eq!(fetcher.default_page_size(), 1_000_000);
+        fetcher.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100).await.unwrap();
+        fetcher.get_datasets().await.unwrap();
+        let requests = stand_in.requests();
+        assert_eq!(requests.len(), 6);
+        assert!(requests.iter().all(|request| request.ends_with("uid=secret-key")));
+    }
+
+    #[tokio::test]
+    async fn clamps_pages_to_the_cell_limit() {
+        let stand_in = NomisStandIn::start().await;
+        stand_in.cap_records(25_000);
+        let data = test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100_000).await.unwrap();
+        assert_eq!(data, stand_in.full_table("NM_144_1"));
+        assert_eq!(stand_in.requests().len(), 1);
+        assert!(stand_in.requests()[0].contains("recordlimit=25000"));
+    }
+
+    #[tokio::test]
+    async fn rejected_api_key_fails_without_retrying() {
+        let stand_in = NomisStandIn::start().await;
+        let mut table = String::from("GEOGRAPHY_NAME,GEOGRAPHY_TYPE,RURAL_URBAN_NAME,CELL_NAME,MEASURES_NAME,OBS_VALUE,OBS_STATUS,RECORD_OFFSET,RECORD_COUNT\n");
+        for index in 0..30_000 {
+            table.push_str(&format!("E{:08},2011 output areas,Total,All usual residents,Value,1,A,0,0\n", index));
+        }
+        stand_in.add_table("NM_144_1", &table);
+        stand_in.cap_records(25_000);
+        let fetcher = test_fetcher(&stand_in).with_api_key("invalid-key");
+        assert!(fetcher.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, fetcher.default_page_size()).await.is_err());
+        assert_eq!(stand_in.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sdmx_and_csv_formats_match() {
+        let stand_in = NomisStandIn::start().await;
+        let fetcher = test_fetcher(&stand_in);
+        let records = fetcher.get_table_sdmx("NM_144_1".to_string(), &GeographySelector::england_output_areas(), 100).await.unwrap();
+        assert_eq!(records.len(), 500);
+        let csv_data = stand_in.full_table("NM_144_1");
+        let csv_records: Vec<PreProcessingRecord> = csv::Reader::from_reader(csv_data.as_bytes()).deserialize().map(|record| record.unwrap()).collect();
+        // JSON numbers lose trailing zeros, such as `0.00` becoming `0.0`, so compare values numerically
+        for (sdmx, csv) in records.iter().zip(csv_records.iter()) {
+            assert_eq!(sdmx.obs_value.parse::<f64>().ok(), csv.obs_value.parse::<f64>().ok());
+            assert_eq!(
+                (&sdmx.geography_name, &sdmx.geography_type, &sdmx.rural_urban_name, &sdmx.cell_name, &sdmx.measures_name, &sdmx.obs_status, sdmx.record_offset, sdmx.record_count),
+                (&csv.geography_name, &csv.geography_type, &csv.rural_urban_name, &csv.cell_name, &csv.measures_name, &csv.obs_status, csv.record_offset, csv.record_count)
+            );
+        }
+        let tables = fetcher.check_formats_match::<PopulationRecord>("NM_144_1".to_string(), &GeographySelector::england_output_areas(), 100).await.unwrap();
+        assert!(!tables.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sdmx_and_csv_formats_match_for_named_geographies() {
+        let stand_in = NomisStandIn::start().await;
+        stand_in.add_table("NM_144_1", include_str!("../data/fixtures/NM_144_1_lsoa.data.csv"));
+        let tables = test_fetcher(&stand_in).check_formats_match::<PopulationRecord>("NM_144_1".to_string(), &GeographySelector::Codes(vec![String::from("E01011954"), String::from("E01011955")]), 100).await.unwrap();
+        assert_eq!(tables.len(), 2);
+        assert_eq!(tables["Hartlepool 001A"].population_counts[AreaClassification::Total][PersonType::All], 1553);
+    }
+
+    #[tokio::test]
+    async fn sdmx_download_pages_past_the_server_cap() {
+        let stand_in = NomisStandIn::start().await;
+        stand_in.cap_records(60);
+        let records = test_fetcher(&stand_in).get_table_sdmx("NM_144_1".to_string(), &GeographySelector::england_output_areas(), 100).await.unwrap();
+        assert_eq!(records.len(), 500);
+        assert_eq!(records[499].record_offset, 499);
+    }
+
+    #[test]
+    fn reads_record_count_from_first_page() {
+        let page = "\"GEOGRAPHY_NAME\",\"RECORD_OFFSET\",\"RECORD_COUNT\"\n\"E00062207\",0,35645376\n";
+        assert_eq!(DataFetcher::read_record_count(page).unwrap(), 35645376);
+        assert_eq!(DataFetcher::read_record_count("\"GEOGRAPHY_NAME\",\"RECORD_COUNT\"\n").unwrap(), 0);
+        assert!(DataFetcher::read_record_count("\"GEOGRAPHY_NAME\"\n\"E00062207\"\n").is_err());
+
+        assert_eq!(DataFetcher::record_counts(page, None).unwrap(), (35645376, 35645376));
+        assert_eq!(DataFetcher::record_counts(page, Some(120)).unwrap(), (35645376, 120));
+        assert_eq!(DataFetcher::record_counts("\"GEOGRAPHY_NAME\"\n", Some(120)).unwrap(), (120, 120));
+        assert!(DataFetcher::record_counts("\"GEOGRAPHY_NAME\"\n", None).is_err());
+    }
+
+    #[test]
+    fn splits_records_into_pages() {
+        assert_eq!(DataFetcher::page_count(35645376, 25000), 1426);
+        assert_eq!(DataFetcher::page_count(500, 100), 5);
+        assert_eq!(DataFetcher::page_count(0, 100), 1);
+        assert_eq!(DataFetcher::first_page_limit(None, 100), 100);
+        assert_eq!(DataFetcher::first_page_limit(Some(30), 100), 30);
+        assert_eq!((0..3).map(|index| DataFetcher::page_limit(120, 50, index)).collect::<Vec<usize>>(), vec![50, 50, 20]);
+    }
+
+    #[tokio::test]
+    async fn record_count_override_limits_pages() {
+        let stand_in = NomisStandIn::start().await;
+        let data = test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), Some(100), 50).await.unwrap();
+        assert_eq!(data.lines().count(), 101);
+        assert_eq!(stand_in.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn record_count_override_need_not_fill_pages() {
+        for (number_of_records, requests) in [(120, 3), (30, 1), (1000, 10)] {
+            let stand_in = NomisStandIn::start().await;
+            let full_table = stand_in.full_table("NM_144_1");
+            let data = test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), Some(number_of_records), 50).await.unwrap();
+            let expected_rows = number_of_records.min(500);
+            assert_eq!(data.lines().collect::<Vec<&str>>(), full_table.lines().take(1 + expected_rows).collect::<Vec<&str>>());
+            assert_eq!(stand_in.requests().len(), requests);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let stand_in = NomisStandIn::start().await;
+        stand_in.fail_next(503, 1);
+        stand_in.fail_next(429, 1);
+        let data = test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.unwrap();
+        assert_eq!(data, stand_in.full_table("NM_144_1"));
+        assert_eq!(stand_in.requests().len(), 4);
+    }
+
+    #[test]
+    fn rejects_truncated_pages() {
+        let fetcher = DataFetcher::default();
+        let page = "\"GEOGRAPHY_NAME\",\"OBS_VALUE\"\n\"E00062207\",242\n\"E00062207\",116\n";
+        fetcher.check_page_complete(page, true, 2).unwrap();
+        assert!(fetcher.check_page_complete(&page[..page.len() - 1], true, 2).is_err());
+        assert!(fetcher.check_page_complete(page, true, 3).is_err());
+        fetcher.check_page_complete("\"E00062207\",242\n", false, 1).unwrap();
+        fetcher.check_page_complete("", false, 0).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let stand_in = NomisStandIn::start().await;
+        stand_in.fail_next(500, 3);
+        assert!(test_fetcher(&stand_in).get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.is_err());
+        assert_eq!(stand_in.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_missing_table() {
+        let stand_in = NomisStandIn::start().await;
+        assert!(test_fetcher(&stand_in).get_table("NM_0_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.is_err());
+        assert_eq!(stand_in.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn resumes_checkpointed_download() {
+        let stand_in = NomisStandIn::start().await;
+        let directory = temp_directory("resume");
+        let fetcher = test_fetcher(&stand_in);
+        let first = fetcher.download_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100, directory.to_str().unwrap()).await.unwrap();
+        assert_eq!(stand_in.requests().len(), 5);
+        std::fs::remove_file(directory.join("page_3.csv")).unwrap();
+        let checkpoint = std::fs::read_to_string(directory.join("checkpoint.json")).unwrap();
+        let checkpoint = checkpoint.replace("    3,\n", "");
+        std::fs::write(directory.join("checkpoint.json"), checkpoint).unwrap();
+        let second = fetcher.download_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100, directory.to_str().unwrap()).await.unwrap();
+        assert_eq!(first, second);
+        assert_eq!(stand_in.requests().len(), 7);
+        assert!(stand_in.requests()[6].contains("RecordOffset=300"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn restarts_checkpoint_for_a_different_record_count() {
+        let stand_in = NomisStandIn::start().await;
+        let directory = temp_directory("restart");
+        let fetcher = test_fetcher(&stand_in);
+        let part = fetcher.download_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), Some(120), 50, directory.to_str().unwrap()).await.unwrap();
+        assert_eq!(part.lines().count(), 121);
+        let full = fetcher.download_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 50, directory.to_str().unwrap()).await.unwrap();
+        assert_eq!(full, stand_in.full_table("NM_144_1"));
+        assert_eq!(stand_in.requests().len(), 3 + 10);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn offline_cache_serves_previous_responses() {
+        let stand_in = NomisStandIn::start().await;
+        let directory = temp_directory("cache");
+        let online = test_fetcher(&stand_in).with_cache(directory.to_str().unwrap(), CacheMode::Online).unwrap();
+        let data = online.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.unwrap();
+        let offline = test_fetcher(&stand_in).with_cache(directory.to_str().unwrap(), CacheMode::OfflineOnly).unwrap();
+        assert_eq!(offline.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.unwrap(), data);
+        assert!(offline.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100).await.is_err());
+        assert_eq!(stand_in.requests().len(), 2);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn cache_first_only_requests_missing_pages() {
+        let stand_in = NomisStandIn::start().await;
+        let directory = temp_directory("cache_first");
+        let fetcher = test_fetcher(&stand_in).with_cache(directory.to_str().unwrap(), CacheMode::CacheFirst).unwrap();
+        let data = fetcher.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.unwrap();
+        assert_eq!(fetcher.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 250).await.unwrap(), data);
+        assert_eq!(stand_in.requests().len(), 2);
+        fetcher.get_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), None, 100).await.unwrap();
+        assert_eq!(stand_in.requests().len(), 2 + 5);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parses_geography_hierarchy() {
+        let stand_in = NomisStandIn::start().await;
+        let hierarchy = test_fetcher(&stand_in).get_geography_code("NM_144_1".to_string()).await.unwrap();
+        let output_areas = hierarchy.select("England", GeographyType::OutputArea).unwrap();
+        assert_eq!(output_areas.code, "2092957699TYPE299");
+        assert_eq!(hierarchy.parent(&output_areas.code).unwrap().gss_code.as_deref(), Some("E92000001"));
+        assert_eq!(hierarchy.select("Wales", GeographyType::OutputArea).unwrap().code, "2092957700TYPE299");
+        assert!(hierarchy.select("Wales", GeographyType::Region).is_err());
+        assert_eq!(hierarchy.types_within("2092957699").len(), 5);
+    }
+
+    #[tokio::test]
+    async fn population_parsers_match_table_schema() {
+        let stand_in = NomisStandIn::start().await;
+        let schema = test_fetcher(&stand_in).get_table_schema("NM_144_1".to_string(), &POPULATION_DIMENSIONS).await.unwrap();
+        let cells = schema.required_dimension("cell").unwrap();
+        cells.check_labels::<PersonType>(&["Area (Hectares)", "Density (number of persons per hectare)"]).unwrap();
+        assert!(cells.check_labels::<PersonType>(&[]).is_err());
+        let rural_urban = schema.required_dimension("rural_urban").unwrap();
+        rural_urban.check_labels::<AreaClassification>(&[]).unwrap();
+        assert_eq!(rural_urban.children("100").len(), 4);
+        assert_eq!(rural_urban.get_by_label("Rural village").unwrap().parent_code.as_deref(), Some("200"));
+        assert_eq!(schema.required_dimension("measures").unwrap().get("20100").unwrap().label, "Value");
+        assert!(schema.required_dimension("date").is_err());
+    }
+
+    #[tokio::test]
+    async fn saved_table_verifies_against_manifest() {
+        let stand_in = NomisStandIn::start().await;
+        let directory = temp_directory("manifest");
+        let output = directory.join("NM_144_1.csv");
+        let (data, manifest) = test_fetcher(&stand_in)
+            .save_table("NM_144_1".to_string(), &GeographySelector::england_output_areas(), 100, directory.join("pages").to_str().unwrap(), output.to_str().unwrap(), None)
+            .await
+            .unwrap();
+        assert_eq!(manifest.row_count, 500);
+        assert_eq!(manifest.pages, 5);
+        assert_eq!(manifest.queries.len(), 1);
+        assert!(manifest.queries[0].contains("geography=2092957699TYPE299"));
+        assert_eq!(download_manifest::verify(&output).unwrap(), manifest);
+        assert_eq!(download_manifest::verify_directory(directory.to_str().unwrap()).len(), 1);
+
+        std::fs::write(&output, data.replacen("242", "243", 1)).unwrap();
+        assert!(download_manifest::verify(&output).is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn lists_all_datasets() {
+        let stand_in = NomisStandIn::start().await;
+        let json = test_fetcher(&stand_in).get_datasets().await.unwrap();
+        let tables = DataFetcher::parse_jsontable_list(json).unwrap();
+        assert_eq!(tables.len(), 3);
+    }
+}
@@ -0,0 +1,206 @@
+use reqwest::Url;
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+/// A request to the NOMIS API, built from typed parameters rather than by joining strings
+///
+/// The path is relative to the API root, such as `NM_144_1.data.csv` or `dataset/def.sdmx.json`
+/// There is deliberately no API key parameter, as the URL is logged and used as the cache key, so `DataFetcher` adds the key to each request itself
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NomisQuery {
+    path: String,
+    date: Option<String>,
+    geography: Option<String>,
+    rural_urban: Vec<u32>,
+    cells: Vec<u32>,
+    measures: Vec<u32>,
+    select: Vec<String>,
+    record_limit: Option<usize>,
+    record_offset: Option<usize>,
+    exclude_column_headings: bool,
+    search: Option<String>,
+}
+
+impl NomisQuery {
+    pub fn new(path: &str) -> NomisQuery {
+        NomisQuery { path: path.to_string(), ..Default::default() }
+    }
+    /// A query for the CSV data of the given table, such as `NM_144_1`
+    pub fn table(id: &str) -> NomisQuery {
+        NomisQuery::new(&format!("{}.data.csv", id))
+    }
+    /// The table this query is for, if it is a data query
+    pub fn table_id(&self) -> Option<&str> {
+        self.path.split_once(".data.").map(|(id, _)| id)
+    }
+    /// Changes the path, keeping every parameter, such as to fetch the same data as `.data.sdmx.json`
+    pub fn with_path(mut self, path: &str) -> NomisQuery {
+        self.path = path.to_string();
+        self
+    }
+    /// The date to fetch, such as `latest` or `2011`
+    pub fn with_date(mut self, date: &str) -> NomisQuery {
+        self.date = Some(date.to_string());
+        self
+    }
+    /// The geography codes to fetch, use `GeographySelector::to_parameters` to build this
+    pub fn with_geography(mut self, geography: &str) -> NomisQuery {
+        self.geography = Some(geography.to_string());
+        self
+    }
+    pub fn with_rural_urban(mut self, rural_urban: &[u32]) -> NomisQuery {
+        self.rural_urban = rural_urban.to_vec();
+        self
+    }
+    pub fn with_cells(mut self, cells: &[u32]) -> NomisQuery {
+        self.cells = cells.to_vec();
+        self
+    }
+    /// The measures to fetch, such as 20100 for the value and 20301 for the percentage
+    pub fn with_measures(mut self, measures: &[u32]) -> NomisQuery {
+        self.measures = measures.to_vec();
+        self
+    }
+    /// The columns to return, from a comma separated list such as `SELECTED_COLUMNS`
+    pub fn with_select(mut self, columns: &str) -> NomisQuery {
+        self.select = columns.split(',').map(|column| column.trim().to_string()).filter(|column| !column.is_empty()).collect();
+        self
+    }
+    pub fn with_record_limit(mut self, record_limit: usize) -> NomisQuery {
+        self.record_limit = Some(record_limit);
+        self
+    }
+    pub fn with_record_offset(mut self, record_offset: usize) -> NomisQuery {
+        self.record_offset = Some(record_offset);
+        self
+    }
+    pub fn with_exclude_column_headings(mut self, exclude_column_headings: bool) -> NomisQuery {
+        self.exclude_column_headings = exclude_column_headings;
+        self
+    }
+    /// Filters a dataset listing, such as `c2011*`
+    pub fn with_search(mut self, search: &str) -> NomisQuery {
+        self.search = Some(search.to_string());
+        self
+    }
+    /// Checks the parameters, and builds the encoded URL relative to the given API root
+    pub fn to_url(&self, base_url: &str) -> Result<Url, ParsingError> {
+        self.validate()?;
+        let base = Url::parse(base_url).map_err(|e| invalid_query(format!("Invalid base URL {}: {}", base_url, e)))?;
+        let mut url = base.join(&self.path).map_err(|e| invalid_query(format!("Invalid path {}: {}", self.path, e)))?;
+        {
+            let mut pairs = url.query_pairs_mut();
+            if let Some(date) = &self.date {
+                pairs.append_pair("date", date);
+            }
+            if let Some(geography) = &self.geography {
+                pairs.append_pair("geography", geography);
+            }
+            if !self.rural_urban.is_empty() {
+                pairs.append_pair("rural_urban", &join_codes(&self.rural_urban));
+            }
+            if !self.cells.is_empty() {
+                pairs.append_pair("cell", &join_codes(&self.cells));
+            }
+            if !self.measures.is_empty() {
+                pairs.append_pair("measures", &join_codes(&self.measures));
+            }
+            if let Some(record_limit) = self.record_limit {
+                pairs.append_pair("recordlimit", &record_limit.to_string());
+            }
+            if let Some(record_offset) = self.record_offset {
+                pairs.append_pair("RecordOffset", &record_offset.to_string());
+            }
+            if self.exclude_column_headings {
+                pairs.append_pair("ExcludeColumnHeadings", "true");
+            }
+            if !self.select.is_empty() {
+                pairs.append_pair("select", &self.select.join(","));
+            }
+            if let Some(search) = &self.search {
+                pairs.append_pair("search", search);
+            }
+        }
+        // Don't leave a dangling `?` when there are no parameters
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+        Ok(url)
+    }
+    fn validate(&self) -> Result<(), ParsingError> {
+        if self.path.is_empty() || self.path.contains('?') || self.path.starts_with('/') {
+            return Err(invalid_query(format!("Path {:?} must be relative to the API root, without parameters", self.path)));
+        }
+        if let Some(date) = &self.date {
+            if date.is_empty() {
+                return Err(invalid_query(String::from("Date is empty")));
+            }
+        }
+        if let Some(geography) = &self.geography {
+            if geography.is_empty() {
+                return Err(invalid_query(String::from("Geography is empty")));
+            }
+        }
+        if self.record_limit == Some(0) {
+            return Err(invalid_query(String::from("Record limit must be at least 1")));
+        }
+        // NOMIS column names are upper case, such as `GEOGRAPHY_NAME` or `OBS_VALUE`
+        if let Some(column) = self.select.iter().find(|column| !column.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')) {
+            return Err(invalid_query(format!("Unknown column {:?}", column)));
+        }
+        Ok(())
+    }
+}
+
+fn join_codes(codes: &[u32]) -> String {
+    codes.iter().map(|code| code.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn invalid_query(message: String) -> ParsingError {
+    ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Query")), Some(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NomisQuery;
+
+    const BASE_URL: &str = "https://www.nomisweb.co.uk/api/v01/";
+
+    #[test]
+    fn encodes_every_parameter() {
+        let url = NomisQuery::table("NM_144_1")
+            .with_date("latest")
+            .with_geography("1237321209...1237321232,2092957699TYPE299")
+            .with_rural_urban(&[0])
+            .with_cells(&[0, 7])
+            .with_measures(&[20100])
+            .with_select("GEOGRAPHY_NAME, OBS_VALUE")
+            .with_record_limit(25000)
+            .with_record_offset(50000)
+            .with_exclude_column_headings(true)
+            .to_url(BASE_URL)
+            .unwrap();
+        assert_eq!(url.as_str(), "https://www.nomisweb.co.uk/api/v01/NM_144_1.data.csv?date=latest&geography=1237321209...1237321232%2C2092957699TYPE299&rural_urban=0&cell=0%2C7&measures=20100&recordlimit=25000&RecordOffset=50000&ExcludeColumnHeadings=true&select=GEOGRAPHY_NAME%2COBS_VALUE");
+    }
+
+    #[test]
+    fn leaves_out_unset_parameters() {
+        assert_eq!(NomisQuery::new("dataset/def.sdmx.json").to_url(BASE_URL).unwrap().as_str(), "https://www.nomisweb.co.uk/api/v01/dataset/def.sdmx.json");
+        assert_eq!(NomisQuery::new("dataset/def.sdmx.json").with_search("c2011*").to_url(BASE_URL).unwrap().as_str(), "https://www.nomisweb.co.uk/api/v01/dataset/def.sdmx.json?search=c2011*");
+        assert_eq!(NomisQuery::table("NM_144_1").with_path("NM_144_1.data.sdmx.json").table_id(), Some("NM_144_1"));
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        let table = NomisQuery::table("NM_144_1");
+        assert!(NomisQuery::new("").to_url(BASE_URL).is_err());
+        assert!(NomisQuery::new("NM_144_1.data.csv?geography=1").to_url(BASE_URL).is_err());
+        assert!(NomisQuery::new("/NM_144_1.data.csv").to_url(BASE_URL).is_err());
+        assert!(table.clone().with_record_limit(0).to_url(BASE_URL).is_err());
+        assert!(table.clone().with_select("GEOGRAPHY_NAME,obs value").to_url(BASE_URL).is_err());
+        assert!(table.clone().with_select("OBS_VALUE;RECORD_COUNT").to_url(BASE_URL).is_err());
+        assert!(table.clone().with_date("").to_url(BASE_URL).is_err());
+        assert!(table.clone().with_geography("").to_url(BASE_URL).is_err());
+        assert!(table.to_url("not a url").is_err());
+    }
+}
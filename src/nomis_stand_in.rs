@@ -0,0 +1,269 @@
+//! A local stand in for the NOMIS API, so `DataFetcher` can be tested without touching the network
+//!
+//! Serves `dataset/def.sdmx.json`, the `dataset/{id}/{dimension}.def.sdmx.json` definitions and paged `{id}.data.csv` and `{id}.data.sdmx.json` requests from the fixtures in `data/fixtures`
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const API_PREFIX: &str = "/api/v01/";
+/// The anonymous NOMIS row limit, used when a request doesn't give a `recordlimit`
+const DEFAULT_RECORD_LIMIT: usize = 25000;
+
+const POPULATION_TABLE: &str = include_str!("../data/download/PopulationAndDensityPerEnglandOutputArea(144).csv");
+const DATASET_DEFINITIONS: &str = include_str!("../data/fixtures/dataset_def.sdmx.json");
+const POPULATION_GEOGRAPHY: &str = include_str!("../data/fixtures/NM_144_1_geography.def.sdmx.json");
+const POPULATION_CELLS: &str = include_str!("../data/fixtures/NM_144_1_cell.def.sdmx.json");
+const POPULATION_RURAL_URBAN: &str = include_str!("../data/fixtures/NM_144_1_rural_urban.def.sdmx.json");
+const POPULATION_MEASURES: &str = include_str!("../data/fixtures/NM_144_1_measures.def.sdmx.json");
+
+struct Table {
+    headers: csv::StringRecord,
+    rows: Vec<csv::StringRecord>,
+}
+
+impl Table {
+    fn from_csv(data: &str) -> Table {
+        let mut reader = csv::Reader::from_reader(data.as_bytes());
+        let headers = reader.headers().expect("Fixture table has no headers").clone();
+        let rows = reader.records().map(|row| row.expect("Fixture table has an invalid row")).collect();
+        Table { headers, rows }
+    }
+    /// Renders a single page, in the same format as NOMIS, with `RECORD_OFFSET` and `RECORD_COUNT` matching the fixture rather than the original download
+    fn page(&self, offset: usize, limit: usize, exclude_headings: bool) -> String {
+        let mut writer = csv::WriterBuilder::new()
+            .quote_style(csv::QuoteStyle::NonNumeric)
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(Vec::new());
+        if !exclude_headings {
+            writer.write_record(&self.headers).unwrap();
+        }
+        let offset_column = self.headers.iter().position(|header| header == "RECORD_OFFSET");
+        let count_column = self.headers.iter().position(|header| header == "RECORD_COUNT");
+        let count = self.rows.len().to_string();
+        for (index, row) in self.rows.iter().enumerate().skip(offset).take(limit) {
+            let index = index.to_string();
+            let row: Vec<&str> = row.iter().enumerate().map(|(column, value)| {
+                if Some(column) == offset_column {
+                    index.as_str()
+                } else if Some(column) == count_column {
+                    count.as_str()
+                } else {
+                    value
+                }
+            }).collect();
+            writer.write_record(row).unwrap();
+        }
+        String::from_utf8(writer.into_inner().unwrap()).unwrap()
+    }
+    /// Renders a single page as `.data.sdmx.json`, with the same observations as the CSV page
+    fn sdmx_page(&self, id: &str, offset: usize, limit: usize) -> String {
+        let column = |row: &csv::StringRecord, name: &str| {
+            self.headers.iter().position(|header| header == name).and_then(|index| row.get(index)).unwrap_or("").to_string()
+        };
+        let observations: Vec<Value> = self.rows.iter().skip(offset).take(limit).map(|row| {
+            let obs_value = serde_json::from_str::<Value>(&column(row, "OBS_VALUE")).unwrap_or(Value::Null);
+            // The name and code of an output area are the same, so only fixtures with other geographies need a `GEOGRAPHY_CODE` column
+            let geography_code = Some(column(row, "GEOGRAPHY_CODE")).filter(|code| !code.is_empty()).unwrap_or_else(|| column(row, "GEOGRAPHY_NAME"));
+            json!({
+                "dataset": {"value": id},
+                "geography": {"value": 0, "description": column(row, "GEOGRAPHY_NAME"), "geogcode": geography_code, "geoglevel": column(row, "GEOGRAPHY_TYPE")},
+                "rural_urban": {"value": column(row, "RURAL_URBAN_TYPECODE"), "description": column(row, "RURAL_URBAN_NAME")},
+                "cell": {"value": 0, "description": column(row, "CELL_NAME")},
+                "measures": {"value": 0, "description": column(row, "MEASURES_NAME")},
+                "obs_value": {"value": obs_value},
+                "obs_status": {"value": column(row, "OBS_STATUS"), "description": "Normal Value"},
+                "obs_conf": {"value": false, "description": "Free (free for publication)"},
+            })
+        }).collect();
+        if observations.is_empty() {
+            // NOMIS leaves out the array entirely for an empty page
+            return json!({}).to_string();
+        }
+        json!({ "obs": observations }).to_string()
+    }
+}
+
+#[derive(Default)]
+struct StandInState {
+    tables: HashMap<String, Table>,
+    files: HashMap<String, String>,
+    /// HTTP status codes to reply with, before serving requests normally again
+    failures: VecDeque<u16>,
+    requests: Vec<String>,
+    /// The most rows served for a single request, like the NOMIS cell limit
+    record_cap: Option<usize>,
+    /// How long to wait before replying to each request, so concurrent requests overlap
+    delay: Duration,
+    in_flight: usize,
+    max_in_flight: usize,
+}
+
+pub struct NomisStandIn {
+    address: SocketAddr,
+    state: Arc<Mutex<StandInState>>,
+}
+
+impl NomisStandIn {
+    /// Starts serving the default fixtures on a random local port
+    pub async fn start() -> NomisStandIn {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind stand in server");
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(StandInState::default()));
+        let stand_in = NomisStandIn { address, state: state.clone() };
+        stand_in.add_table("NM_144_1", POPULATION_TABLE);
+        stand_in.add_file("dataset/def.sdmx.json", DATASET_DEFINITIONS);
+        stand_in.add_file("dataset/NM_144_1/geography.def.sdmx.json", POPULATION_GEOGRAPHY);
+        stand_in.add_file("dataset/NM_144_1/cell.def.sdmx.json", POPULATION_CELLS);
+        stand_in.add_file("dataset/NM_144_1/rural_urban.def.sdmx.json", POPULATION_RURAL_URBAN);
+        stand_in.add_file("dataset/NM_144_1/measures.def.sdmx.json", POPULATION_MEASURES);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.expect("Failed to accept connection");
+                tokio::spawn(handle_connection(stream, state.clone()));
+            }
+        });
+        stand_in
+    }
+    /// The URL to give to `DataFetcher::with_base_url`
+    pub fn base_url(&self) -> String {
+        format!("http://{}{}", self.address, API_PREFIX)
+    }
+    /// Serves the given CSV as the table `id`
+    pub fn add_table(&self, id: &str, data: &str) {
+        self.state.lock().unwrap().tables.insert(id.to_string(), Table::from_csv(data));
+    }
+    /// Serves the body at the given path, relative to the API root
+    pub fn add_file(&self, path: &str, body: &str) {
+        self.state.lock().unwrap().files.insert(path.to_string(), body.to_string());
+    }
+    /// Replies to the next `count` requests with the given HTTP status
+    pub fn fail_next(&self, status: u16, count: usize) {
+        self.state.lock().unwrap().failures.extend(std::iter::repeat_n(status, count));
+    }
+    /// Serves at most `cap` rows per request, however many are asked for
+    pub fn cap_records(&self, cap: usize) {
+        self.state.lock().unwrap().record_cap = Some(cap);
+    }
+    /// Waits for the given time before replying to each request
+    pub fn delay_responses(&self, delay: Duration) {
+        self.state.lock().unwrap().delay = delay;
+    }
+    /// The most requests that have been waiting for a reply at once
+    pub fn max_in_flight(&self) -> usize {
+        self.state.lock().unwrap().max_in_flight
+    }
+    /// Every request received so far, as the path and query relative to the API root
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+    /// Renders the whole of a table in a single page, as a sequential download would produce
+    pub fn full_table(&self, id: &str) -> String {
+        let state = self.state.lock().unwrap();
+        let table = &state.tables[id];
+        table.page(0, table.rows.len(), false)
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, state: Arc<Mutex<StandInState>>) {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    while !request.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let target = request.split_whitespace().nth(1).unwrap_or("/");
+    let delay = {
+        let mut state = state.lock().unwrap();
+        state.in_flight += 1;
+        state.max_in_flight = state.max_in_flight.max(state.in_flight);
+        state.delay
+    };
+    tokio::time::sleep(delay).await;
+    let (status, body) = {
+        let mut state = state.lock().unwrap();
+        state.in_flight -= 1;
+        respond(target, &mut state)
+    };
+    let reason = match status {
+        200 => "OK",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        _ => "Error",
+    };
+    let response = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, reason, body.len(), body);
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+fn respond(target: &str, state: &mut StandInState) -> (u16, String) {
+    let target = target.strip_prefix(API_PREFIX).unwrap_or(target);
+    state.requests.push(target.to_string());
+    if let Some(status) = state.failures.pop_front() {
+        return (status, format!("Stand in failure {}", status));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let parameters: HashMap<String, String> = query.split('&').filter_map(|pair| pair.split_once('=')).map(|(key, value)| (percent_decode(key), percent_decode(value))).collect();
+    let offset = parameters.get("RecordOffset").and_then(|value| value.parse().ok()).unwrap_or(0);
+    let limit = parameters.get("recordlimit").and_then(|value| value.parse().ok()).unwrap_or(DEFAULT_RECORD_LIMIT).min(state.record_cap.unwrap_or(usize::MAX));
+    if let Some(id) = path.strip_suffix(".data.sdmx.json") {
+        return match state.tables.get(id) {
+            Some(table) => (200, table.sdmx_page(id, offset, limit)),
+            None => (404, format!("Unknown table {}", id)),
+        };
+    }
+    if let Some(id) = path.strip_suffix(".data.csv") {
+        return match state.tables.get(id) {
+            Some(table) => {
+                let exclude_headings = parameters.get("ExcludeColumnHeadings").map(|value| value == "true").unwrap_or(false);
+                (200, table.page(offset, limit, exclude_headings))
+            }
+            None => (404, format!("Unknown table {}", id)),
+        };
+    }
+    match state.files.get(path) {
+        Some(body) => (200, body.clone()),
+        None => (404, format!("Unknown path {}", path)),
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = bytes.get(index + 1..index + 3).and_then(|hex| std::str::from_utf8(hex).ok()).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(if bytes[index] == b'+' { b' ' } else { bytes[index] });
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::percent_decode;
+
+    #[test]
+    fn decodes_escapes_up_to_the_end() {
+        assert_eq!(percent_decode("E00000001%2CE00000002"), "E00000001,E00000002");
+        assert_eq!(percent_decode("E00000001%2C"), "E00000001,");
+        assert_eq!(percent_decode("%2C"), ",");
+        assert_eq!(percent_decode("GEOGRAPHY+NAME%"), "GEOGRAPHY NAME%");
+        assert_eq!(percent_decode("1%2"), "1%2");
+        assert_eq!(percent_decode("1%ZZ"), "1%ZZ");
+    }
+}
@@ -7,8 +7,13 @@ use crate::parsing_error::ParsingErrorType::{JSONParseError, NetworkError};
 pub enum ParsingErrorType {
     NetworkError,
     JSONParseError,
+    CSVParseError,
     InvalidDataType(String),
     MissingKey,
+    IOError,
+    CacheMiss,
+    /// NOMIS didn't accept the API key, so only returned as many rows as an anonymous request
+    ApiKeyRejected,
 }
 
 impl Display for ParsingErrorType {
@@ -29,11 +34,21 @@ impl ParsingError {
             name,
         }
     }
+    pub(crate) fn error_type(&self) -> &ParsingErrorType {
+        &self.error_type
+    }
 }
 
 impl From<reqwest::Error> for ParsingError {
     fn from(err: reqwest::Error) -> Self {
-        ParsingError { error_type: NetworkError, name: Some(format!("{:?}", err)) }
+        // The URL can contain the API key, so is never included
+        ParsingError { error_type: NetworkError, name: Some(format!("{:?}", err.without_url())) }
+    }
+}
+
+impl From<std::io::Error> for ParsingError {
+    fn from(err: std::io::Error) -> Self {
+        ParsingError { error_type: ParsingErrorType::IOError, name: Some(format!("{:?}", err)) }
     }
 }
 
@@ -43,6 +58,12 @@ impl From<serde_json::Error> for ParsingError {
     }
 }
 
+impl From<csv::Error> for ParsingError {
+    fn from(err: csv::Error) -> Self {
+        ParsingError { error_type: ParsingErrorType::CSVParseError, name: Some(format!("{:?}", err)) }
+    }
+}
+
 impl From<serde_plain::Error> for ParsingError {
     fn from(err: serde_plain::Error) -> Self {
         ParsingError { error_type: JSONParseError, name: Some(format!("{:?}", err)) }
@@ -6,22 +6,23 @@ use log::debug;
 use serde::{Deserialize, Deserializer};
 use serde::de::Error;
 
+use crate::census_table::{build_record, CensusTable};
 use crate::parsing_error::{ParsingError, ParsingErrorType};
 
 pub const SELECTED_COLUMNS: &str = "GEOGRAPHY_NAME,GEOGRAPHY_TYPE,RURAL_URBAN_NAME,RURAL_URBAN_TYPECODE,CELL_NAME,MEASURES_NAME,OBS_VALUE,OBS_STATUS,RECORD_OFFSET,RECORD_COUNT";
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, PartialEq)]
 #[serde(rename_all = "UPPERCASE")]
 pub struct PreProcessingRecord {
     pub geography_name: String,
-    geography_type: String,
-    rural_urban_name: String,
-    cell_name: String,
-    measures_name: String,
-    obs_value: String,
-    obs_status: String,
-    record_offset: u32,
-    record_count: u32,
+    pub geography_type: String,
+    pub rural_urban_name: String,
+    pub cell_name: String,
+    pub measures_name: String,
+    pub obs_value: String,
+    pub obs_status: String,
+    pub record_offset: u32,
+    pub record_count: u32,
 }
 
 #[derive(Deserialize, Debug, Enum)]
@@ -70,55 +71,109 @@ pub enum PersonType {
     Schoolchild,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct PopulationRecord {
     pub geography_code: String,
     pub geography_type: String,
     pub area_size: f32,
     pub density: f32,
-    pub population_counts: EnumMap<AreaClassification, EnumMap<PersonType, u16>>,
+    pub population_counts: EnumMap<AreaClassification, EnumMap<PersonType, u32>>,
+}
+
+
+/// A cell of KS101EW, which has the area and density alongside the person counts
+#[derive(Debug)]
+pub enum PopulationCell {
+    Person(PersonType),
+    AreaSize,
+    Density,
+}
+
+impl<'de> Deserialize<'de> for PopulationCell {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let cell_name = String::deserialize(deserializer)?;
+        match cell_name.as_str() {
+            "Area (Hectares)" => Ok(PopulationCell::AreaSize),
+            "Density (number of persons per hectare)" => Ok(PopulationCell::Density),
+            _ => serde_plain::from_str(&cell_name).map(PopulationCell::Person).map_err(D::Error::custom),
+        }
+    }
 }
 
+impl CensusTable for PopulationRecord {
+    const TABLE_ID: &'static str = "NM_144_1";
+    type Cell = PopulationCell;
+    type Value = f64;
+
+    fn new(geography_code: String, geography_type: String) -> Self {
+        PopulationRecord { geography_code, geography_type, area_size: 0.0, density: 0.0, population_counts: EnumMap::default() }
+    }
+    fn geography_code(&self) -> &str {
+        &self.geography_code
+    }
+    fn add(&mut self, record: &PreProcessingRecord, cell: PopulationCell, value: f64) -> Result<(), ParsingError> {
+        match cell {
+            PopulationCell::AreaSize => self.area_size = value as f32,
+            PopulationCell::Density => self.density = value as f32,
+            PopulationCell::Person(person_classification) => {
+                let area_classification: AreaClassification = serde_plain::from_str(&record.rural_urban_name)?;
+                self.population_counts[area_classification][person_classification] = count(record, value)?;
+            }
+        }
+        Ok(())
+    }
+    fn validate(&self) -> Result<(), ParsingError> {
+        debug!("New record: Code: {}, data: {:?}", self.geography_code, &self.population_counts[AreaClassification::Total]);
+        Ok(())
+    }
+}
+
+/// Converts a value to a count, failing rather than rounding or clamping it
+fn count(record: &PreProcessingRecord, value: f64) -> Result<u32, ParsingError> {
+    if value.fract() != 0.0 || value < 0.0 || value > u32::MAX as f64 {
+        return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Count")), Some(format!("Invalid count {:?} for {} in {}", record.obs_value, record.cell_name, record.geography_name))));
+    }
+    Ok(value as u32)
+}
 
 impl TryFrom<Vec<PreProcessingRecord>> for PopulationRecord {
     type Error = ParsingError;
 
     fn try_from(records: Vec<PreProcessingRecord>) -> Result<Self, Self::Error> {
-        if records.is_empty() {
-            return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from("Array is empty")), Some(String::from("Need at least one record, to build a Population Record"))));
-        }
-        let geography_code = String::from(&records[0].geography_name);
-        let geography_type = String::from(&records[0].geography_type);
-        let mut area_size: f32 = 0.0;
-        let mut density: f32 = 0.0;
-        let mut data: EnumMap<AreaClassification, EnumMap<PersonType, u16>> = EnumMap::default();
-        for record in records {
-            if record.geography_name != geography_code {
-                return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from(&record.geography_name)), Some(format!("Mis matching geography codes: {} and {}", geography_code, record.geography_name))));
-            }
-            if record.geography_type != geography_type {
-                return Err(ParsingError::new(ParsingErrorType::InvalidDataType(String::from(&record.geography_type)), Some(format!("Mis matching geography type: {} and {}", geography_type, record.geography_type))));
-            }
-            if record.measures_name == "Value" {
-                if &record.cell_name == "Area (Hectares)" {
-                    area_size = record.obs_value.parse().unwrap_or(0.0);
-                } else if &record.cell_name == "Density (number of persons per hectare)" {
-                    density = record.obs_value.parse().unwrap_or(0.0);
-                } else {
-                    let area_classification: AreaClassification = serde_plain::from_str(&record.rural_urban_name)?;
-                    let person_classification: PersonType = serde_plain::from_str(&record.cell_name)?;
-                    data[area_classification][person_classification] = record.obs_value.parse()?;
-                }
-            }
+        build_record(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::{AreaClassification, PersonType, PopulationRecord, PreProcessingRecord};
+
+    fn records(count: &str) -> Vec<PreProcessingRecord> {
+        vec![PreProcessingRecord {
+            geography_name: String::from("E06000005"),
+            geography_type: String::from("local authorities: district / unitary"),
+            rural_urban_name: String::from("Total"),
+            cell_name: String::from("All usual residents"),
+            measures_name: String::from("Value"),
+            obs_value: count.to_string(),
+            obs_status: String::from("A"),
+            record_offset: 0,
+            record_count: 1,
+        }]
+    }
+
+    #[test]
+    fn keeps_counts_above_u16() {
+        let record = PopulationRecord::try_from(records("105564")).unwrap();
+        assert_eq!(record.population_counts[AreaClassification::Total][PersonType::All], 105564);
+    }
+
+    #[test]
+    fn rejects_counts_that_are_not_whole_numbers() {
+        for count in ["12.5", "-3", "1e12", "NaN"] {
+            assert!(PopulationRecord::try_from(records(count)).is_err(), "{}", count);
         }
-        debug!("New record: Code: {}, data: {:?}", geography_code, &data[AreaClassification::Total]);
-
-        Ok(PopulationRecord {
-            geography_code,
-            geography_type,
-            area_size,
-            density,
-            population_counts: data,
-        })
     }
-}
\ No newline at end of file
+}
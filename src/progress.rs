@@ -0,0 +1,191 @@
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+/// The shortest time between two updates of the same task, so fast loops don't flood the reporter
+const REPORT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How far through a long running task is, such as downloading pages or drawing output areas
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub task: String,
+    /// The number of units in the task, if it is known in advance
+    pub total: Option<usize>,
+    pub finished: usize,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    /// Finished units per second
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 { self.finished as f64 / seconds } else { 0.0 }
+    }
+    /// The estimated time until every unit is finished, at the throughput so far
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total?.saturating_sub(self.finished);
+        let throughput = self.throughput();
+        if throughput > 0.0 { Some(Duration::from_secs_f64(remaining as f64 / throughput)) } else { None }
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.total {
+            Some(total) => write!(f, "{}: {}/{}", self.task, self.finished, total)?,
+            None => write!(f, "{}: {}", self.task, self.finished)?,
+        }
+        write!(f, " in {:.1?} ({:.1}/s", self.elapsed, self.throughput())?;
+        if let Some(eta) = self.eta() {
+            write!(f, ", {:.0?} remaining", eta)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Receives progress updates from downloads, parsing, map loading and drawing
+///
+/// Implement this to show progress in your own UI
+pub trait ProgressReporter: Send + Sync {
+    fn update(&self, progress: &Progress);
+    /// Called once, when the task has finished
+    fn finish(&self, progress: &Progress);
+}
+
+/// Redraws a single status line on stderr
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TerminalProgress;
+
+impl ProgressReporter for TerminalProgress {
+    fn update(&self, progress: &Progress) {
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{}\x1b[K", progress);
+        let _ = stderr.flush();
+    }
+    fn finish(&self, progress: &Progress) {
+        eprintln!("\r{}\x1b[K", progress);
+    }
+}
+
+/// Logs each update at the info level
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogProgress;
+
+impl ProgressReporter for LogProgress {
+    fn update(&self, progress: &Progress) {
+        info!("{}", progress);
+    }
+    fn finish(&self, progress: &Progress) {
+        info!("Finished {}", progress);
+    }
+}
+
+/// Ignores every update
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressReporter for NoProgress {
+    fn update(&self, _progress: &Progress) {}
+    fn finish(&self, _progress: &Progress) {}
+}
+
+/// Counts finished units of a single task, and passes the progress on to a reporter at most every `REPORT_INTERVAL`
+///
+/// Units can be added from several concurrent requests at once
+pub struct ProgressTracker<'a> {
+    reporter: &'a dyn ProgressReporter,
+    task: String,
+    total: AtomicUsize,
+    finished: AtomicUsize,
+    start_time: Instant,
+    last_report: Mutex<Option<Instant>>,
+}
+
+/// Stored in `total` when the total isn't known
+const UNKNOWN_TOTAL: usize = usize::MAX;
+
+impl<'a> ProgressTracker<'a> {
+    pub fn new(reporter: &'a dyn ProgressReporter, task: &str, total: Option<usize>) -> ProgressTracker<'a> {
+        ProgressTracker {
+            reporter,
+            task: task.to_string(),
+            total: AtomicUsize::new(total.unwrap_or(UNKNOWN_TOTAL)),
+            finished: AtomicUsize::new(0),
+            start_time: Instant::now(),
+            last_report: Mutex::new(None),
+        }
+    }
+    /// Sets the total, such as once the first page of a download has given the record count
+    pub fn set_total(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+    pub fn add(&self, units: usize) {
+        self.finished.fetch_add(units, Ordering::Relaxed);
+        let mut last_report = self.last_report.lock().unwrap();
+        if last_report.is_none_or(|last| last.elapsed() >= REPORT_INTERVAL) {
+            *last_report = Some(Instant::now());
+            self.reporter.update(&self.progress());
+        }
+    }
+    pub fn progress(&self) -> Progress {
+        let total = self.total.load(Ordering::Relaxed);
+        Progress {
+            task: self.task.clone(),
+            total: if total == UNKNOWN_TOTAL { None } else { Some(total) },
+            finished: self.finished.load(Ordering::Relaxed),
+            elapsed: self.start_time.elapsed(),
+        }
+    }
+    pub fn finish(self) {
+        self.reporter.finish(&self.progress());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use super::{Progress, ProgressReporter, ProgressTracker};
+
+    #[derive(Default)]
+    struct RecordingProgress {
+        updates: Mutex<Vec<Progress>>,
+        finished: Mutex<Option<Progress>>,
+    }
+
+    impl ProgressReporter for RecordingProgress {
+        fn update(&self, progress: &Progress) {
+            self.updates.lock().unwrap().push(progress.clone());
+        }
+        fn finish(&self, progress: &Progress) {
+            *self.finished.lock().unwrap() = Some(progress.clone());
+        }
+    }
+
+    #[test]
+    fn reports_throughput_and_eta() {
+        let progress = Progress { task: String::from("Drawing"), total: Some(300), finished: 100, elapsed: Duration::from_secs(10) };
+        assert_eq!(progress.throughput(), 10.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(20)));
+        assert_eq!(Progress { total: None, ..progress }.eta(), None);
+    }
+
+    #[test]
+    fn tracker_throttles_updates_and_reports_finish() {
+        let reporter = RecordingProgress::default();
+        let tracker = ProgressTracker::new(&reporter, "Parsing", None);
+        for _ in 0..1000 {
+            tracker.add(1);
+        }
+        tracker.set_total(1000);
+        tracker.finish();
+        assert_eq!(reporter.updates.lock().unwrap().len(), 1);
+        let finished = reporter.finished.lock().unwrap().clone().unwrap();
+        assert_eq!((finished.finished, finished.total), (1000, Some(1000)));
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How failed requests to NOMIS are retried
+///
+/// Delays grow exponentially from `initial_delay`, capped at `max_delay`, with full jitter applied so concurrent requests don't retry in lockstep
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that gives up after the first failure
+    pub fn no_retries() -> RetryPolicy {
+        RetryPolicy { max_retries: 0, ..Default::default() }
+    }
+    /// Picks how long to wait before the given retry attempt (starting at 0)
+    pub fn delay_for_attempt(&self, attempt: u32) -> Duration {
+        let exponential = self.initial_delay.saturating_mul(2_u32.saturating_pow(attempt));
+        let capped = exponential.min(self.max_delay);
+        capped.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Spaces out requests so no more than the given number are started each second
+///
+/// Shared between every request made by a `DataFetcher`, however many are in flight at once
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_request: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Creates a new limiter, where a rate of 0 disables limiting
+    pub fn new(requests_per_second: f64) -> RateLimiter {
+        let interval = if requests_per_second > 0.0 { Duration::from_secs_f64(1.0 / requests_per_second) } else { Duration::ZERO };
+        RateLimiter { interval, next_request: Mutex::new(Instant::now()) }
+    }
+    /// Waits until the next request is allowed to start
+    pub async fn wait(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+        let mut next_request = self.next_request.lock().await;
+        let now = Instant::now();
+        if *next_request > now {
+            tokio::time::sleep_until(*next_request).await;
+        }
+        *next_request = (*next_request).max(now) + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{RateLimiter, RetryPolicy};
+
+    #[test]
+    fn delays_grow_exponentially_up_to_the_cap() {
+        let policy = RetryPolicy { max_retries: 10, initial_delay: Duration::from_millis(100), max_delay: Duration::from_secs(1) };
+        for attempt in 0..10 {
+            let cap = Duration::from_millis(100 * 2_u64.pow(attempt)).min(Duration::from_secs(1));
+            assert!((0..20).all(|_| policy.delay_for_attempt(attempt) <= cap));
+        }
+        assert!(policy.delay_for_attempt(u32::MAX) <= Duration::from_secs(1));
+        assert_eq!(RetryPolicy::no_retries().max_retries, 0);
+    }
+
+    #[tokio::test]
+    async fn spaces_out_requests() {
+        let limiter = RateLimiter::new(50.0);
+        let start = Instant::now();
+        for _ in 0..6 {
+            limiter.wait().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        let unlimited = RateLimiter::new(0.0);
+        let start = Instant::now();
+        for _ in 0..100 {
+            unlimited.wait().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
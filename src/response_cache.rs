@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::PathBuf;
+
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::download_checkpoint::write_atomically;
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+
+/// How the `DataFetcher` uses its response cache
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Always makes requests, and stores every response in the cache
+    Online,
+    /// Uses a cached response if there is one, otherwise makes the request and caches the result
+    CacheFirst,
+    /// Never makes requests, and fails if a response isn't in the cache
+    OfflineOnly,
+}
+
+/// A content addressed on disk store of NOMIS responses, keyed by the SHA-256 of the full request URL
+#[derive(Clone, Debug)]
+pub struct ResponseCache {
+    directory: PathBuf,
+    mode: CacheMode,
+}
+
+impl ResponseCache {
+    pub fn new(directory: &str, mode: CacheMode) -> Result<ResponseCache, ParsingError> {
+        let directory = PathBuf::from(directory);
+        fs::create_dir_all(&directory)?;
+        Ok(ResponseCache { directory, mode })
+    }
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+    /// Returns the cached response for the given URL, if there is one
+    pub fn get(&self, url: &str) -> Result<Option<String>, ParsingError> {
+        let path = self.path_for(url);
+        if path.exists() {
+            debug!("Cache hit for {} at {:?}", url, path);
+            Ok(Some(fs::read_to_string(path)?))
+        } else {
+            debug!("Cache miss for {}", url);
+            Ok(None)
+        }
+    }
+    /// Returns the cached response for the given URL, or a `CacheMiss` error if it isn't cached
+    pub fn get_required(&self, url: &str) -> Result<String, ParsingError> {
+        self.get(url)?.ok_or_else(|| ParsingError::new(ParsingErrorType::CacheMiss, Some(format!("No cached response for {} in {:?}", url, self.directory))))
+    }
+    pub fn put(&self, url: &str, data: &str) -> Result<(), ParsingError> {
+        write_atomically(&self.path_for(url), data.as_bytes())
+    }
+    fn path_for(&self, url: &str) -> PathBuf {
+        let hash: String = Sha256::digest(url.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.directory.join(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::{CacheMode, ResponseCache};
+
+    fn temp_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("response_cache_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        directory
+    }
+
+    #[test]
+    fn stores_responses_by_url() {
+        let directory = temp_directory("store");
+        let cache = ResponseCache::new(directory.to_str().unwrap(), CacheMode::CacheFirst).unwrap();
+        let url = "https://www.nomisweb.co.uk/api/v01/NM_144_1.data.csv?recordlimit=100";
+        assert_eq!(cache.get(url).unwrap(), None);
+        assert!(cache.get_required(url).is_err());
+        cache.put(url, "GEOGRAPHY_NAME\nE00062207\n").unwrap();
+        assert_eq!(cache.get_required(url).unwrap(), "GEOGRAPHY_NAME\nE00062207\n");
+        assert_eq!(cache.get(&format!("{}&RecordOffset=100", url)).unwrap(), None);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writes_to_one_url_do_not_collide() {
+        let directory = temp_directory("concurrent");
+        let cache = Arc::new(ResponseCache::new(directory.to_str().unwrap(), CacheMode::Online).unwrap());
+        let url = "https://www.nomisweb.co.uk/api/v01/NM_144_1.data.csv";
+        let writers: Vec<_> = (0..8).map(|writer| {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    cache.put(url, &writer.to_string().repeat(10_000)).unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let data = cache.get_required(url).unwrap();
+        assert_eq!(data.len(), 10_000);
+        assert!(data.chars().all(|c| c == data.chars().next().unwrap()));
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
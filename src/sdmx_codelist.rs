@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::nomis_download::{extract_array_from_json, extract_string_from_json, extract_value_from_json};
+use crate::parsing_error::ParsingError;
+
+/// A single code from a NOMIS `*.def.sdmx.json` codelist
+#[derive(Clone, Debug)]
+pub struct CodelistEntry {
+    /// The code NOMIS uses in queries
+    pub value: String,
+    pub description: String,
+    pub parent_code: Option<String>,
+    /// The annotation text, keyed by annotation title
+    pub annotations: HashMap<String, String>,
+}
+
+/// Parses every code from the first codelist in a `*.def.sdmx.json` response
+pub fn parse_codelist(json: &Value) -> Result<Vec<CodelistEntry>, ParsingError> {
+    let structure = extract_value_from_json(json, "structure")?;
+    let codelists = extract_value_from_json(structure, "codelists")?;
+    let codelist = extract_array_from_json(codelists, "codelist")?;
+    let mut entries = Vec::new();
+    for list in codelist {
+        for code in extract_array_from_json(list, "code")? {
+            entries.push(parse_code(code)?);
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_code(code: &Value) -> Result<CodelistEntry, ParsingError> {
+    let value = extract_string_from_json(code, "value")?;
+    let description = extract_string_from_json(extract_value_from_json(code, "description")?, "value")?;
+    let parent_code = match code.get("parentcode") {
+        Some(_) => Some(extract_string_from_json(code, "parentcode")?),
+        None => None,
+    };
+    let mut annotations = HashMap::new();
+    if let Some(annotation) = code.get("annotations").and_then(|annotations| annotations.get("annotation")) {
+        // A single annotation isn't wrapped in an array
+        let annotation = match annotation {
+            Value::Array(array) => array.iter().collect(),
+            other => vec![other],
+        };
+        for annotation in annotation {
+            let title = extract_string_from_json(annotation, "annotationtitle")?;
+            let text = extract_string_from_json(annotation, "annotationtext")?;
+            annotations.insert(title, text);
+        }
+    }
+    Ok(CodelistEntry { value, description, parent_code, annotations })
+}
@@ -0,0 +1,118 @@
+use serde_json::Value;
+
+use crate::nomis_download::{extract_array_from_json, extract_string_from_json, extract_value_from_json};
+use crate::parsing_error::ParsingError;
+use crate::population_and_density_per_output_area::PreProcessingRecord;
+
+/// A dimension of an observation from a `.data.sdmx.json` response, such as `cell` or `measures`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DimensionValue {
+    /// The code NOMIS uses in queries
+    pub code: String,
+    pub description: String,
+}
+
+/// A single observation from a `.data.sdmx.json` response, with its code lists and attributes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    pub geography: DimensionValue,
+    /// The ONS code for the geography, such as `E00062207`
+    pub geography_code: String,
+    /// The name of the geography type, such as "2011 output areas"
+    pub geography_type: String,
+    pub rural_urban: DimensionValue,
+    pub cell: DimensionValue,
+    pub measures: DimensionValue,
+    /// Empty if the value has been suppressed
+    pub obs_value: String,
+    pub obs_status: DimensionValue,
+    pub obs_conf: Option<DimensionValue>,
+}
+
+impl Observation {
+    fn from_json(obs: &Value) -> Result<Observation, ParsingError> {
+        let geography = extract_value_from_json(obs, "geography")?;
+        let obs_value = optional_string(extract_value_from_json(obs, "obs_value")?, "value")?.unwrap_or_default();
+        Ok(Observation {
+            geography: dimension_value(obs, "geography")?,
+            geography_code: optional_string(geography, "geogcode")?.unwrap_or_default(),
+            geography_type: optional_string(geography, "geoglevel")?.unwrap_or_default(),
+            rural_urban: dimension_value(obs, "rural_urban")?,
+            cell: dimension_value(obs, "cell")?,
+            measures: dimension_value(obs, "measures")?,
+            obs_value,
+            obs_status: dimension_value(obs, "obs_status")?,
+            obs_conf: match obs.get("obs_conf") {
+                Some(_) => Some(dimension_value(obs, "obs_conf")?),
+                None => None,
+            },
+        })
+    }
+    /// Converts to the same long format record as a row of the CSV download
+    ///
+    /// The CSV `GEOGRAPHY_NAME` is the geography's description, such as "Hartlepool 001A" for an LSOA, which is only the same as its code for output areas
+    pub fn to_record(&self, record_offset: u32, record_count: u32) -> PreProcessingRecord {
+        let geography_name = if self.geography.description.is_empty() { &self.geography_code } else { &self.geography.description };
+        PreProcessingRecord {
+            geography_name: geography_name.to_string(),
+            geography_type: self.geography_type.to_string(),
+            rural_urban_name: self.rural_urban.description.to_string(),
+            cell_name: self.cell.description.to_string(),
+            measures_name: self.measures.description.to_string(),
+            obs_value: self.obs_value.to_string(),
+            obs_status: self.obs_status.code.to_string(),
+            record_offset,
+            record_count,
+        }
+    }
+}
+
+/// Parses the `obs` array of a `.data.sdmx.json` response
+pub fn parse_observations(json: &Value) -> Result<Vec<Observation>, ParsingError> {
+    match json.get("obs") {
+        // NOMIS leaves out the array entirely if a page has no observations
+        None => Ok(Vec::new()),
+        Some(_) => extract_array_from_json(json, "obs")?.iter().map(Observation::from_json).collect(),
+    }
+}
+
+fn dimension_value(obs: &Value, name: &str) -> Result<DimensionValue, ParsingError> {
+    let dimension = extract_value_from_json(obs, name)?;
+    Ok(DimensionValue {
+        code: optional_string(dimension, "value")?.unwrap_or_default(),
+        description: optional_string(dimension, "description")?.unwrap_or_default(),
+    })
+}
+
+fn optional_string(object: &Value, name: &str) -> Result<Option<String>, ParsingError> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(value)) => Ok(Some(value.to_string())),
+        Some(_) => Ok(Some(extract_string_from_json(object, name)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use crate::population_and_density_per_output_area::PreProcessingRecord;
+
+    use super::parse_observations;
+
+    #[test]
+    fn records_match_csv_rows_for_named_geographies() {
+        let json: Value = serde_json::from_str(include_str!("../data/fixtures/NM_144_1_lsoa.data.sdmx.json")).unwrap();
+        let observations = parse_observations(&json).unwrap();
+        assert_eq!(observations[0].geography_code, "E01011954");
+        let csv_records: Vec<PreProcessingRecord> = csv::Reader::from_reader(include_str!("../data/fixtures/NM_144_1_lsoa.data.csv").as_bytes()).deserialize().map(|record| record.unwrap()).collect();
+        assert_eq!(observations.len(), csv_records.len());
+        for (offset, (observation, csv)) in observations.iter().zip(csv_records.iter()).enumerate() {
+            let mut record = observation.to_record(offset as u32, csv_records.len() as u32);
+            // JSON numbers lose trailing zeros, such as `62.70` becoming `62.7`, so compare values numerically
+            assert_eq!(record.obs_value.parse::<f64>().unwrap(), csv.obs_value.parse::<f64>().unwrap());
+            record.obs_value = csv.obs_value.clone();
+            assert_eq!(&record, csv);
+        }
+    }
+}
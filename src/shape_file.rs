@@ -5,12 +5,6 @@ extern crate polylabel;
 
 
 use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::error::Error;
-use std::fmt::{Debug, Display, Formatter};
-use std::time::Instant;
-
-use csv::StringRecord;
 use geo_types::{Coordinate, LineString};
 use plotters::coord::Shift;
 use plotters::prelude::*;
@@ -19,7 +13,7 @@ use shapefile::dbase::{FieldValue, Record};
 use shapefile::Shape;
 
 use crate::population_and_density_per_output_area::{AreaClassification, PersonType, PopulationRecord};
-use crate::shape_file::DwellingType::{BlockOfFlats, Commercial, DetachedHouse, FlatMaisonetteApartment, SemiDetachedHouse, SharedHouse, Temporary, TerracedHouse};
+use crate::progress::{ProgressReporter, ProgressTracker};
 
 const DEBUG_ITERATION: usize = 5000;
 pub const GRID_SIZE: u32 = 16384;
@@ -117,16 +111,15 @@ impl Map {
     fn default() -> Map {
         Map { data: Vec::default(), min_x: i32::MAX, min_y: i32::MAX, max_x: i32::MIN, max_y: i32::MIN }
     }
-    pub(crate) fn from_file(filename: &str) -> Map {
+    pub(crate) fn from_file(filename: &str, progress: &dyn ProgressReporter) -> Map {
         //let filename="census_map_areas/England_wa_2011/england_wa_2011.shp";
 
         let mut map = Map::default();
         let mut reader =
             shapefile::Reader::from_path(filename)
                 .unwrap();
-        let start_time = Instant::now();
-        println!("Loading map data from file...");
-        for (index, shape_record) in reader.iter_shapes_and_records().enumerate() {
+        let progress = ProgressTracker::new(progress, "Loading map data", None);
+        for shape_record in reader.iter_shapes_and_records() {
             let (shape, record) = shape_record.unwrap();
             if let Shape::Polygon(polygon) = shape {
                 assert!(!polygon.rings().is_empty());
@@ -144,22 +137,19 @@ impl Map {
             } else {
                 panic!("Unexpected shape: {}", shape);
             }
-            if index % DEBUG_ITERATION == 0 {
-                println!("  At index {} with time {:?}", index, start_time.elapsed());
-            }
+            progress.add(1);
         }
-        println!("Finished loading map data in {:?}", start_time.elapsed());
+        progress.finish();
         map
     }
 
-    fn draw_with_labels<T: plotters::prelude::DrawingBackend>(&self, drawing_area: DrawingArea<T, Shift>) {
-        self.draw(drawing_area, true);
+    fn draw_with_labels<T: plotters::prelude::DrawingBackend>(&self, drawing_area: DrawingArea<T, Shift>, progress: &dyn ProgressReporter) {
+        self.draw(drawing_area, true, progress);
     }
-    pub(crate) fn draw<T: plotters::prelude::DrawingBackend>(&self, drawing_area: DrawingArea<T, Shift>, show_labels: bool) {
-        let start_time = Instant::now();
-        println!("Drawing output areas on map...");
+    pub(crate) fn draw<T: plotters::prelude::DrawingBackend>(&self, drawing_area: DrawingArea<T, Shift>, show_labels: bool, progress: &dyn ProgressReporter) {
+        let progress = ProgressTracker::new(progress, "Drawing output areas", Some(self.data.len()));
         let style = TextStyle::from(("sans-serif", 20).into_font()).color(&RED);
-        for (index, data) in self.data.iter().enumerate() {
+        for data in self.data.iter() {
             if show_labels {
                 let centre = data.centre.unwrap_or_else(|| data.get_centre_point());
                 let centre = convert_geo_point_to_pixel(centre);
@@ -198,17 +188,14 @@ impl Map {
                     }
                 }
             }
-            if index % DEBUG_ITERATION == 0 {
-                println!("  At index {} with time {:?}", index, start_time.elapsed());
-            }
+            progress.add(1);
         }
         drawing_area.present().unwrap();
-        println!("Finished drawing in {:?}", start_time.elapsed());
+        progress.finish();
     }
-    pub(crate) fn draw_test<T: plotters::prelude::DrawingBackend>(&self, drawing_area: DrawingArea<T, Shift>, show_labels: bool, census_data: HashMap<String, PopulationRecord>) {
-        let start_time = Instant::now();
+    pub(crate) fn draw_test<T: plotters::prelude::DrawingBackend>(&self, drawing_area: DrawingArea<T, Shift>, show_labels: bool, census_data: HashMap<String, PopulationRecord>, progress: &dyn ProgressReporter) {
         let mut chart = ChartBuilder::on(&drawing_area).build_cartesian_2d(0..(GRID_SIZE as i32), 0..(GRID_SIZE as i32)).unwrap();
-        println!("Drawing output areas on map...");
+        let progress = ProgressTracker::new(progress, "Drawing output areas", Some(self.data.len()));
         let style = TextStyle::from(("sans-serif", 20).into_font()).color(&RED);
         for (index, data) in self.data.iter().enumerate() {
             if show_labels {
@@ -229,93 +216,12 @@ impl Map {
             }
             chart.draw_series(std::iter::once(plotters::prelude::Polygon::new(polygon, &plotters::style::RGBColor(colour, 0, 0)))).unwrap();
 
+            progress.add(1);
             if index % DEBUG_ITERATION == 0 {
-                println!("  At index {} with time {:?}", index, start_time.elapsed());
                 return;
             }
         }
         drawing_area.present().unwrap();
-        println!("Finished drawing in {:?}", start_time.elapsed());
-    }
-}
-
-enum ParsingErrors {
-    MissingDate,
-    MissingGeographyCode,
-}
-
-impl Debug for ParsingErrors {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Shit fucked")
-    }
-}
-
-impl Display for ParsingErrors {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Shit fucked")
-    }
-}
-
-impl Error for ParsingErrors {}
-
-enum DwellingType {
-    DetachedHouse,
-    SemiDetachedHouse,
-    TerracedHouse,
-    FlatMaisonetteApartment,
-    BlockOfFlats,
-    SharedHouse,
-    Commercial,
-    Temporary,
-}
-
-impl TryFrom<&str> for DwellingType {
-    type Error = String;
-
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Detached; measures: Value" => Ok(DetachedHouse),
-            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Semi-detached; measures: Value" => Ok(SemiDetachedHouse),
-            "Dwelling Type: Unshared dwelling: Whole house or bungalow: Terraced (including end-terrace); measures: Value" => Ok(TerracedHouse),
-            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: Total; measures: Value" => Ok(FlatMaisonetteApartment),
-            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: Purpose-built block of flats or tenement; measures: Value" => Ok(BlockOfFlats),
-            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: Part of a converted or shared house (including bed-sits); measures: Value" => Ok(SharedHouse),
-            "Dwelling Type: Unshared dwelling: Flat, maisonette or apartment: In commercial building; measures: Value" => Ok(Commercial),
-            "Dwelling Type: Unshared dwelling: Caravan or other mobile or temporary structure; measures: Value" => Ok(Temporary),
-
-            _ => Err(format!("Cannot convert value ({}) to Dwelling type ", value))
-        }
-    }
-}
-
-struct HouseholdType {
-    date: String,
-    oa_code: String,
-    dwelling_type: HashMap<DwellingType, u16>,
-}
-
-impl HouseholdType {
-    fn new(date: String, oa_code: String) -> HouseholdType {
-        HouseholdType {
-            date,
-            oa_code,
-            dwelling_type: Default::default(),
-        }
-    }
-}
-
-fn read_csv(filename: &str) -> Result<(), Box<dyn Error>> {
-    let mut reader = csv::Reader::from_path(filename)?;
-    let headers: Vec<&str> = reader.headers()?.iter().map(|s| s).collect();
-    let mut outputVec: Vec<HouseholdType> = Vec::new();
-    for record in reader.records() {
-        let record = record?;
-        let household = HouseholdType::new(record.get(0).ok_or(ParsingErrors::MissingDate)?.to_string(), record.get(2).ok_or(ParsingErrors::MissingGeographyCode)?.to_string());
-        for (index, value) in record.iter().enumerate() {
-            if index < 4 {
-                continue;
-            }
-        }
+        progress.finish();
     }
-    Ok(())
 }
@@ -0,0 +1,197 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::path::Path;
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::download_checkpoint::write_atomically;
+use crate::nomis_download::{DataFetcher, TableInfo};
+use crate::parsing_error::ParsingError;
+
+/// Filters for searching a `TableCatalogue`, where every filter that is set must match
+///
+/// All comparisons ignore case
+#[derive(Clone, Debug, Default)]
+pub struct CatalogueQuery {
+    keyword: Option<String>,
+    mnemonic: Option<String>,
+    source: Option<String>,
+    geography_level: Option<String>,
+}
+
+impl CatalogueQuery {
+    /// Matches tables with a keyword containing the given text
+    pub fn with_keyword(mut self, keyword: &str) -> CatalogueQuery {
+        self.keyword = Some(keyword.to_lowercase());
+        self
+    }
+    /// Matches the table with the given mnemonic, such as `c2011ks101ew`
+    pub fn with_mnemonic(mut self, mnemonic: &str) -> CatalogueQuery {
+        self.mnemonic = Some(mnemonic.to_lowercase());
+        self
+    }
+    /// Matches tables from the given source, such as `census_2011_qs`
+    pub fn with_source(mut self, source: &str) -> CatalogueQuery {
+        self.source = Some(source.to_lowercase());
+        self
+    }
+    /// Matches tables available at the given geography level, such as `oa`
+    pub fn with_geography_level(mut self, geography_level: &str) -> CatalogueQuery {
+        self.geography_level = Some(geography_level.to_lowercase());
+        self
+    }
+    pub fn matches(&self, table: &TableInfo) -> bool {
+        if let Some(keyword) = &self.keyword {
+            if !table.keywords.iter().any(|table_keyword| table_keyword.to_lowercase().contains(keyword)) {
+                return false;
+            }
+        }
+        if let Some(mnemonic) = &self.mnemonic {
+            if &table.coded_name.to_lowercase() != mnemonic {
+                return false;
+            }
+        }
+        if let Some(source) = &self.source {
+            if &table.source.to_lowercase() != source {
+                return false;
+            }
+        }
+        if let Some(geography_level) = &self.geography_level {
+            if !table.geo_level.iter().any(|level| &level.to_lowercase() == geography_level) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The tables that were added or removed between two versions of a catalogue
+#[derive(Debug, Default)]
+pub struct CatalogueDiff {
+    pub added: Vec<TableInfo>,
+    pub removed: Vec<TableInfo>,
+}
+
+impl CatalogueDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl Display for CatalogueDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} tables added, {} tables removed", self.added.len(), self.removed.len())?;
+        for table in &self.added {
+            writeln!(f, "  + {}", table)?;
+        }
+        for table in &self.removed {
+            writeln!(f, "  - {}", table)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every census table NOMIS provides, which can be saved to disk and searched offline
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TableCatalogue {
+    pub tables: Vec<TableInfo>,
+}
+
+impl TableCatalogue {
+    pub fn new(tables: Vec<TableInfo>) -> TableCatalogue {
+        TableCatalogue { tables }
+    }
+    /// Downloads and parses the dataset definitions from NOMIS
+    pub async fn fetch(fetcher: &DataFetcher) -> Result<TableCatalogue, ParsingError> {
+        let json = fetcher.get_datasets().await?;
+        Ok(TableCatalogue::new(DataFetcher::parse_jsontable_list(json)?))
+    }
+    pub fn load(filename: &str) -> Result<TableCatalogue, ParsingError> {
+        let file = File::open(filename)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+    pub fn save(&self, filename: &str) -> Result<(), ParsingError> {
+        let data = serde_json::to_string_pretty(self)?;
+        write_atomically(Path::new(filename), data.as_bytes())
+    }
+    /// Fetches the latest catalogue, saves it over the one at `filename`, and reports what has changed since that was saved
+    pub async fn refresh(fetcher: &DataFetcher, filename: &str) -> Result<(TableCatalogue, CatalogueDiff), ParsingError> {
+        let previous = if Path::new(filename).exists() { TableCatalogue::load(filename)? } else { TableCatalogue::default() };
+        let latest = TableCatalogue::fetch(fetcher).await?;
+        let diff = previous.diff(&latest);
+        info!("Refreshed catalogue at {}: {} tables added, {} tables removed", filename, diff.added.len(), diff.removed.len());
+        latest.save(filename)?;
+        Ok((latest, diff))
+    }
+    pub fn search(&self, query: &CatalogueQuery) -> Vec<&TableInfo> {
+        self.tables.iter().filter(|table| query.matches(table)).collect()
+    }
+    pub fn get(&self, id: &str) -> Option<&TableInfo> {
+        self.tables.iter().find(|table| table.id == id)
+    }
+    pub fn get_by_mnemonic(&self, mnemonic: &str) -> Option<&TableInfo> {
+        self.search(&CatalogueQuery::default().with_mnemonic(mnemonic)).into_iter().next()
+    }
+    /// Compares this catalogue with a newer version, by table id
+    pub fn diff(&self, newer: &TableCatalogue) -> CatalogueDiff {
+        let old_ids: HashSet<&str> = self.tables.iter().map(|table| table.id.as_str()).collect();
+        let new_ids: HashSet<&str> = newer.tables.iter().map(|table| table.id.as_str()).collect();
+        CatalogueDiff {
+            added: newer.tables.iter().filter(|table| !old_ids.contains(table.id.as_str())).cloned().collect(),
+            removed: self.tables.iter().filter(|table| !new_ids.contains(table.id.as_str())).cloned().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nomis_download::{DataFetcher, TableInfo};
+
+    use super::{CatalogueQuery, TableCatalogue};
+
+    fn catalogue() -> TableCatalogue {
+        let json = serde_json::from_str(include_str!("../data/fixtures/dataset_def.sdmx.json")).unwrap();
+        TableCatalogue::new(DataFetcher::parse_jsontable_list(json).unwrap())
+    }
+
+    fn ids(tables: Vec<&TableInfo>) -> Vec<&str> {
+        tables.iter().map(|table| table.id.as_str()).collect()
+    }
+
+    #[test]
+    fn searches_by_each_filter() {
+        let catalogue = catalogue();
+        assert_eq!(ids(catalogue.search(&CatalogueQuery::default().with_keyword("age"))), vec!["NM_503_1", "NM_1001_1"]);
+        assert_eq!(ids(catalogue.search(&CatalogueQuery::default().with_mnemonic("C2011KS101EW"))), vec!["NM_144_1"]);
+        assert_eq!(ids(catalogue.search(&CatalogueQuery::default().with_source("census_2011_qs"))), vec!["NM_503_1"]);
+        assert_eq!(ids(catalogue.search(&CatalogueQuery::default().with_geography_level("oa"))), vec!["NM_144_1", "NM_503_1"]);
+        assert_eq!(ids(catalogue.search(&CatalogueQuery::default().with_keyword("age").with_geography_level("la"))), vec!["NM_1001_1"]);
+        assert!(catalogue.search(&CatalogueQuery::default().with_mnemonic("c2011ks101")).is_empty());
+        assert_eq!(catalogue.get_by_mnemonic("c2011qs103ew").unwrap().id, "NM_503_1");
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let catalogue = catalogue();
+        let filename = std::env::temp_dir().join(format!("table_catalogue_{}.json", std::process::id()));
+        catalogue.save(filename.to_str().unwrap()).unwrap();
+        let loaded = TableCatalogue::load(filename.to_str().unwrap()).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&catalogue).unwrap());
+        assert!(catalogue.diff(&loaded).is_empty());
+    }
+
+    #[test]
+    fn diff_reports_added_and_removed_tables() {
+        let newer = catalogue();
+        let mut older = catalogue();
+        older.tables.retain(|table| table.id != "NM_503_1");
+        older.tables.push(TableInfo { id: String::from("NM_0_1"), ..newer.tables[0].clone() });
+        let diff = older.diff(&newer);
+        assert_eq!(ids(diff.added.iter().collect()), vec!["NM_503_1"]);
+        assert_eq!(ids(diff.removed.iter().collect()), vec!["NM_0_1"]);
+        assert!(diff.to_string().starts_with("1 tables added, 1 tables removed"));
+    }
+}
@@ -0,0 +1,82 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::parsing_error::{ParsingError, ParsingErrorType};
+use crate::sdmx_codelist::parse_codelist;
+
+/// The dimensions of the population and density table (`NM_144_1`)
+pub const POPULATION_DIMENSIONS: [&str; 3] = ["cell", "rural_urban", "measures"];
+
+/// A single code of a table dimension, such as the `Males` cell
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DimensionCode {
+    /// The code NOMIS uses in queries
+    pub code: String,
+    /// The label NOMIS uses in the `*_NAME` CSV columns
+    pub label: String,
+    pub parent_code: Option<String>,
+    /// The position of this code in the NOMIS definition, which is the order the codes are returned in
+    pub order: usize,
+}
+
+/// Every code of a single dimension of a table, from a `{dimension}.def.sdmx.json` response
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dimension {
+    /// The name NOMIS uses for the dimension, such as `cell` or `rural_urban`
+    pub name: String,
+    pub codes: Vec<DimensionCode>,
+}
+
+impl Dimension {
+    pub fn from_json(name: &str, json: &Value) -> Result<Dimension, ParsingError> {
+        let codes = parse_codelist(json)?.into_iter().enumerate().map(|(order, entry)| DimensionCode {
+            code: entry.value,
+            label: entry.description,
+            parent_code: entry.parent_code,
+            order,
+        }).collect();
+        Ok(Dimension { name: name.to_string(), codes })
+    }
+    pub fn get(&self, code: &str) -> Option<&DimensionCode> {
+        self.codes.iter().find(|dimension_code| dimension_code.code == code)
+    }
+    pub fn get_by_label(&self, label: &str) -> Option<&DimensionCode> {
+        self.codes.iter().find(|dimension_code| dimension_code.label == label)
+    }
+    pub fn children(&self, code: &str) -> Vec<&DimensionCode> {
+        self.codes.iter().filter(|dimension_code| dimension_code.parent_code.as_deref() == Some(code)).collect()
+    }
+    /// The codes whose labels can't be parsed as `T`, such as a cell missing from `PersonType`
+    pub fn unknown_labels<T: DeserializeOwned>(&self) -> Vec<&DimensionCode> {
+        self.codes.iter().filter(|dimension_code| serde_plain::from_str::<T>(&dimension_code.label).is_err()).collect()
+    }
+    /// Checks every label, apart from those in `ignored`, can be parsed as `T`
+    pub fn check_labels<T: DeserializeOwned>(&self, ignored: &[&str]) -> Result<(), ParsingError> {
+        let unknown: Vec<&str> = self.unknown_labels::<T>().into_iter()
+            .map(|dimension_code| dimension_code.label.as_str())
+            .filter(|label| !ignored.contains(label))
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(ParsingError::new(ParsingErrorType::InvalidDataType(format!("Dimension {}", self.name)), Some(format!("Unknown labels: {:?}", unknown))))
+        }
+    }
+}
+
+/// The dimension definitions of a single table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableSchema {
+    pub table_id: String,
+    pub dimensions: Vec<Dimension>,
+}
+
+impl TableSchema {
+    pub fn dimension(&self, name: &str) -> Option<&Dimension> {
+        self.dimensions.iter().find(|dimension| dimension.name == name)
+    }
+    /// Like `dimension`, but fails if the table doesn't have the dimension
+    pub fn required_dimension(&self, name: &str) -> Result<&Dimension, ParsingError> {
+        self.dimension(name).ok_or_else(|| ParsingError::new(ParsingErrorType::MissingKey, Some(format!("{} has no {} dimension", self.table_id, name))))
+    }
+}
@@ -0,0 +1,143 @@
+use enum_map::EnumMap;
+use serde::Deserialize;
+
+use crate::census_table::{census_table, check_totals};
+use crate::parsing_error::ParsingError;
+
+/// The cells of QS701EW, the main method of travel to work of usual residents aged 16 to 74
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Enum)]
+pub enum TravelMethod {
+    #[serde(alias = "All categories: Method of travel to work")]
+    All,
+    #[serde(alias = "Work mainly at or from home")]
+    WorkFromHome,
+    #[serde(alias = "Underground, metro, light rail, tram")]
+    Underground,
+    #[serde(alias = "Train")]
+    Train,
+    #[serde(alias = "Bus, minibus or coach")]
+    Bus,
+    #[serde(alias = "Taxi")]
+    Taxi,
+    #[serde(alias = "Motorcycle, scooter or moped")]
+    Motorcycle,
+    #[serde(alias = "Driving a car or van")]
+    CarDriver,
+    #[serde(alias = "Passenger in a car or van")]
+    CarPassenger,
+    #[serde(alias = "Bicycle")]
+    Bicycle,
+    #[serde(alias = "On foot")]
+    OnFoot,
+    #[serde(alias = "Other method of travel to work")]
+    Other,
+    #[serde(alias = "Not in employment")]
+    NotInEmployment,
+}
+
+impl TravelMethod {
+    pub fn parent(self) -> Option<TravelMethod> {
+        match self {
+            TravelMethod::All => None,
+            _ => Some(TravelMethod::All),
+        }
+    }
+    /// Whether this is a way of working, rather than the total or the residents not in employment
+    pub fn is_mode(self) -> bool {
+        !matches!(self, TravelMethod::All | TravelMethod::NotInEmployment)
+    }
+}
+
+census_table! {
+    /// The usual residents aged 16 to 74 of a single area by method of travel to work, from QS701EW (`NM_568_1`)
+    pub struct TravelToWorkRecord("NM_568_1", TravelMethod, u32, validate = check_travel_totals);
+}
+
+fn check_travel_totals(record: &TravelToWorkRecord) -> Result<(), ParsingError> {
+    check_totals(&record.geography_code, &record.values, TravelMethod::parent)
+}
+
+impl TravelToWorkRecord {
+    /// The residents in employment, including those working from home
+    pub fn working_residents(&self) -> u32 {
+        self.values[TravelMethod::All].saturating_sub(self.values[TravelMethod::NotInEmployment])
+    }
+    pub fn mode_share(&self) -> ModeShare {
+        let working_residents = self.working_residents();
+        let mut shares = EnumMap::default();
+        if working_residents > 0 {
+            for (method, count) in self.values.iter().filter(|(method, _)| method.is_mode()) {
+                shares[method] = *count as f64 / working_residents as f64;
+            }
+        }
+        ModeShare { geography_code: self.geography_code.clone(), working_residents, shares }
+    }
+}
+
+/// The share of an area's working residents using each method of travel to work
+///
+/// The shares of every mode sum to 1, unless the area has no working residents, while `All` and `NotInEmployment` are always 0
+/// Join to a `PopulationRecord` with `census_table::join_by_area`, as both are keyed by geography code
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModeShare {
+    pub geography_code: String,
+    pub working_residents: u32,
+    pub shares: EnumMap<TravelMethod, f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::census_table::join_by_area;
+    use crate::census_table::tests::{cell_rows, parse_rows};
+    use crate::nomis_download::DataFetcher;
+    use crate::population_and_density_per_output_area::PopulationRecord;
+    use crate::progress::NoProgress;
+
+    use super::{TravelMethod, TravelToWorkRecord};
+
+    const CELLS: [(&str, u32); 13] = [
+        ("All categories: Method of travel to work", 100),
+        ("Work mainly at or from home", 4),
+        ("Underground, metro, light rail, tram", 10),
+        ("Train", 6),
+        ("Bus, minibus or coach", 10),
+        ("Taxi", 0),
+        ("Motorcycle, scooter or moped", 0),
+        ("Driving a car or van", 20),
+        ("Passenger in a car or van", 2),
+        ("Bicycle", 3),
+        ("On foot", 5),
+        ("Other method of travel to work", 0),
+        ("Not in employment", 40),
+    ];
+
+    fn parse(code: &str, cells: &[(&str, u32)]) -> HashMap<String, TravelToWorkRecord> {
+        parse_rows(&cell_rows(code, cells))
+    }
+
+    #[test]
+    fn mode_shares_sum_to_one_over_working_residents() {
+        let records = parse("E00000001", &CELLS);
+        let share = records["E00000001"].mode_share();
+        assert_eq!(share.working_residents, 60);
+        assert_eq!(share.shares[TravelMethod::CarDriver], 20.0 / 60.0);
+        assert_eq!(share.shares[TravelMethod::NotInEmployment], 0.0);
+        assert!((share.shares.values().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let mut wrong_total = CELLS;
+        wrong_total[12].1 = 41;
+        assert!(parse("E00000001", &wrong_total).is_empty());
+    }
+
+    #[test]
+    fn joins_mode_shares_to_population_by_area() {
+        let population: HashMap<String, PopulationRecord> = DataFetcher::parse_table(csv::Reader::from_reader(include_str!("../data/download/PopulationAndDensityPerEnglandOutputArea(144).csv").as_bytes()), &NoProgress).unwrap();
+        let shares: HashMap<String, _> = parse("E00062207", &CELLS).into_iter().map(|(code, record)| (code, record.mode_share())).collect();
+        let joined = join_by_area(&population, &shares);
+        assert_eq!(joined.len(), 1);
+        let (population, share) = joined["E00062207"];
+        assert_eq!(population.geography_code, share.geography_code);
+    }
+}